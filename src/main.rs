@@ -1,8 +1,87 @@
 use eframe::{egui, App, Frame};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::fs::File;
+use std::hash::Hash;
 use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// Default sizes (in canvas units) for newly created nodes.
+const DEFAULT_NOTE_SIZE: egui::Vec2 = egui::vec2(200.0, 40.0);
+const DEFAULT_CODE_SIZE: egui::Vec2 = egui::vec2(300.0, 40.0);
+// New image nodes get this width and the picture's aspect ratio.
+const DEFAULT_IMAGE_WIDTH: f32 = 200.0;
+
+// Fraction of the pan inertia velocity that remains after one second of gliding.
+const PAN_FRICTION: f32 = 0.02;
+
+// Space (in pixels) left around the board when fitting it to the window.
+const FIT_MARGIN: f32 = 40.0;
+
+// Default distance between grid lines in canvas units.
+const GRID_SPACING: f32 = 25.0;
+
+// Most grid lines drawn along one axis before the grid is skipped altogether.
+const MAX_GRID_LINES: f32 = 2000.0;
+
+// Smallest on-screen spacing (in pixels) at which the dot grid is still drawn; below it the
+// dots would blur into a fill and number in the millions.
+const MIN_DOT_GRID_SPACING: f32 = 4.0;
+
+// Largest side (in pixels) of a rendered board image and of a saved thumbnail.
+const MAX_EXPORT_SIZE: f32 = 8192.0;
+const THUMBNAIL_SIZE: f32 = 256.0;
+
+// How long an edit (typing, dragging a color picker) must pause before it's committed
+// to the undo stack as its own entry.
+const EDIT_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// How often the files of synced code nodes are checked for changes.
+const CODE_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// Default maximum distance (in screen pixels) from an arrow or stroke at which a click
+// still selects it; adjustable from the Tools bar.
+const CONNECTION_HIT_RADIUS: f32 = 6.0;
+
+// Outline color for selected nodes and connections.
+const SELECTION_COLOR: egui::Color32 = egui::Color32::from_rgb(97, 175, 239);
+
+// eframe storage key of the last session (the full project history, as JSON).
+const SESSION_KEY: &str = "session";
+
+// Outline color of nodes without connections, when orphan highlighting is on.
+const ORPHAN_COLOR: egui::Color32 = egui::Color32::from_rgb(224, 108, 117);
+
+// Outline color of nodes matching the search text.
+const SEARCH_COLOR: egui::Color32 = egui::Color32::from_rgb(152, 195, 121);
+
+// Color of measurement lines and their labels.
+const MEASURE_COLOR: egui::Color32 = egui::Color32::from_rgb(229, 192, 123);
+
+// eframe storage key of the user's `Preferences`.
+const PREFERENCES_KEY: &str = "preferences";
+
+// eframe storage key of whether the Tools overlay was open ("true" or "false").
+const TOOLS_OPEN_KEY: &str = "tools_open";
+
+// Default background of note nodes.
+const NOTE_BACKGROUND: egui::Color32 = egui::Color32::from_rgb(32, 37, 43);
+
+// Default text color of note nodes.
+const NOTE_TEXT_COLOR: egui::Color32 = egui::Color32::from_rgb(187, 192, 206);
+
+// How long a toast notification stays on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+
+// Number of messages kept in the log panel; older ones are dropped.
+const MAX_LOG_ENTRIES: usize = 200;
+
+// Number of straight segments an ellipse outline is drawn with.
+const ELLIPSE_SEGMENTS: usize = 48;
+
+// Prefix of the clipboard text of a copied code or image node, followed by its JSON.
+const COPIED_NODE_PREFIX: &str = "cnf-infinity-node:";
 
 mod ser_de {
     use egui::{Color32, Pos2, Vec2};
@@ -25,6 +104,24 @@ mod ser_de {
         Ok(Color32::from_rgba_premultiplied(r, g, b, a))
     }
 
+    // Serialize an optional Color32 as (r, g, b, a) or null.
+    pub fn serialize_color_opt<S>(color: &Option<Color32>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        color
+            .map(|c| (c.r(), c.g(), c.b(), c.a()))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize_color_opt<'de, D>(deserializer: D) -> Result<Option<Color32>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tup = Option::<(u8, u8, u8, u8)>::deserialize(deserializer)?;
+        Ok(tup.map(|(r, g, b, a)| Color32::from_rgba_premultiplied(r, g, b, a)))
+    }
+
     // Serialize a Pos2 as (x, y)
     pub fn serialize_pos2<S>(pos: &Pos2, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -59,6 +156,23 @@ mod ser_de {
         Ok(Vec2::new(x, y))
     }
 
+    // Deserialize a zoom saved either as (x, y) or, by older versions, as a single number.
+    pub fn deserialize_zoom<'de, D>(deserializer: D) -> Result<Vec2, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Zoom {
+            Uniform(f32),
+            Axes(f32, f32),
+        }
+        Ok(match Zoom::deserialize(deserializer)? {
+            Zoom::Uniform(zoom) => Vec2::splat(zoom),
+            Zoom::Axes(x, y) => Vec2::new(x, y),
+        })
+    }
+
     // Serialize a Vec<Pos2> as a Vec of (x, y) tuples.
     pub fn serialize_pos2_vec<S>(vec: &Vec<Pos2>, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -101,10 +215,217 @@ mod ser_de {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+// Software rendering primitives for drawing the board into an image (PNG export, thumbnails).
+mod raster {
+    use ab_glyph::FontArc;
+    use egui::{Color32, Pos2, Rect};
+    use image::{Rgba, RgbaImage};
+    use imageproc::drawing::{self, Blend};
+    use imageproc::point::Point;
+
+    // Image that alpha-blends everything drawn onto it.
+    pub type Canvas = Blend<RgbaImage>;
+
+    fn rgba(color: Color32) -> Rgba<u8> {
+        Rgba(color.to_srgba_unmultiplied())
+    }
+
+    pub fn new_canvas(width: u32, height: u32, background: Color32) -> Canvas {
+        Blend(RgbaImage::from_pixel(
+            width.max(1),
+            height.max(1),
+            rgba(background),
+        ))
+    }
+
+    // The monospace font egui renders node text with.
+    pub fn monospace_font() -> Option<FontArc> {
+        let fonts = egui::FontDefinitions::default();
+        let data = fonts.font_data.get("Hack")?;
+        FontArc::try_from_vec(data.font.to_vec()).ok()
+    }
+
+    // Draw a polyline `width` pixels wide with round joints.
+    pub fn polyline(canvas: &mut Canvas, points: &[Pos2], width: f32, color: Color32) {
+        let color = rgba(color);
+        let radius = width / 2.0;
+        for segment in points.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            if radius < 1.0 {
+                drawing::draw_line_segment_mut(canvas, (a.x, a.y), (b.x, b.y), color);
+                continue;
+            }
+            if (b - a).length() >= 0.5 {
+                let normal = (b - a).normalized().rot90() * radius;
+                let quad = [a + normal, b + normal, b - normal, a - normal]
+                    .map(|p| Point::new(p.x.round() as i32, p.y.round() as i32));
+                if quad[0] != quad[3] {
+                    drawing::draw_polygon_mut(canvas, &quad, color);
+                }
+            }
+        }
+        if radius >= 1.0 {
+            for p in points {
+                let center = (p.x.round() as i32, p.y.round() as i32);
+                drawing::draw_filled_circle_mut(canvas, center, radius.round() as i32, color);
+            }
+        }
+    }
+
+    // Fill `rect` and outline it with a one pixel border.
+    pub fn rect(canvas: &mut Canvas, rect: Rect, fill: Color32, border: Color32) {
+        let (width, height) = (rect.width().round() as u32, rect.height().round() as u32);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let area = imageproc::rect::Rect::at(rect.min.x.round() as i32, rect.min.y.round() as i32)
+            .of_size(width, height);
+        drawing::draw_filled_rect_mut(canvas, area, rgba(fill));
+        drawing::draw_hollow_rect_mut(canvas, area, rgba(border));
+    }
+
+    // Draw `picture` scaled to fill `rect`.
+    pub fn image(canvas: &mut Canvas, rect: Rect, picture: &RgbaImage) {
+        let (width, height) = (rect.width().round() as u32, rect.height().round() as u32);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let scaled = image::imageops::resize(
+            picture,
+            width,
+            height,
+            image::imageops::FilterType::Triangle,
+        );
+        image::imageops::overlay(
+            &mut canvas.0,
+            &scaled,
+            rect.min.x.round() as i64,
+            rect.min.y.round() as i64,
+        );
+    }
+
+    // Draw a single line of text centered on `center` over a background box.
+    pub fn label(
+        canvas: &mut Canvas,
+        font: &FontArc,
+        center: Pos2,
+        size: f32,
+        color: Color32,
+        background: Color32,
+        text: &str,
+    ) {
+        let (width, height) = drawing::text_size(size, font, text);
+        let text_rect = Rect::from_center_size(center, egui::vec2(width as f32, size * 1.2));
+        rect(canvas, text_rect.expand(size * 0.3), background, background);
+        let y = center.y - height as f32 / 2.0;
+        drawing::draw_text_mut(
+            canvas,
+            rgba(color),
+            text_rect.min.x.round() as i32,
+            y.round() as i32,
+            size,
+            font,
+            text,
+        );
+    }
+
+    // Draw lines of monospace text starting at the top-left of `clip`, dropping lines and
+    // characters that don't fit inside it.
+    pub fn text_block(
+        canvas: &mut Canvas,
+        font: &FontArc,
+        clip: Rect,
+        size: f32,
+        color: Color32,
+        text: &str,
+    ) {
+        if size < 1.0 {
+            return;
+        }
+        let line_height = size * 1.2;
+        let max_chars = (clip.width() / (size * 0.6)).floor().max(0.0) as usize;
+        for (i, line) in text.lines().enumerate() {
+            let y = clip.min.y + i as f32 * line_height;
+            if y + line_height > clip.max.y {
+                break;
+            }
+            let visible: String = line.chars().take(max_chars).collect();
+            drawing::draw_text_mut(
+                canvas,
+                rgba(color),
+                clip.min.x.round() as i32,
+                y.round() as i32,
+                size,
+                font,
+                &visible,
+            );
+        }
+    }
+}
+
+// Standard base64 with padding, for image data embedded in project files.
+mod base64 {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn encode(bytes: &[u8]) -> String {
+        let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for chunk in bytes.chunks(3) {
+            let byte = |i: usize| chunk.get(i).copied().unwrap_or(0) as u32;
+            let group = (byte(0) << 16) | (byte(1) << 8) | byte(2);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(ALPHABET[((group >> (18 - 6 * i)) & 63) as usize] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    // `None` if `text` contains anything but base64 characters and trailing padding.
+    pub fn decode(text: &str) -> Option<Vec<u8>> {
+        let text = text.trim_end_matches('=');
+        let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for c in text.bytes() {
+            let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+            buffer = (buffer << 6) | value;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+            }
+        }
+        Some(bytes)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 enum NodeType {
     Note,
     Code,
+    Image,
+}
+
+impl NodeType {
+    // Name used for the node type in CSV exports.
+    fn name(self) -> &'static str {
+        match self {
+            NodeType::Note => "note",
+            NodeType::Code => "code",
+            NodeType::Image => "image",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "note" => Some(NodeType::Note),
+            "code" => Some(NodeType::Code),
+            "image" => Some(NodeType::Image),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -115,8 +436,42 @@ enum Side {
     Right,
 }
 
+// Canvas tool deciding what clicks and drags do. With no tool the canvas pans and arrows
+// can be clicked; `Select` picks whichever entity is under the cursor.
+#[derive(Clone, Copy, PartialEq)]
+enum Tool {
+    None,
+    Select,
+    Marker,
+    Highlighter,
+    Eraser,
+    Arrow,
+    Measure,
+    Line,
+    Rect,
+    Ellipse,
+}
+
+// Actions that replace the board, which ask first when there are unsaved changes.
+#[derive(Clone, Copy)]
+enum FileAction {
+    New,
+    Open,
+    OpenWithoutHistory,
+}
+
+// Entity under the cursor, as found by `MyApp::hit_test`.
+enum CanvasHit {
+    Node(NodeType, usize),
+    Connection(usize),
+    Stroke(usize),
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 struct NodeConnection {
+    // Stable id used to target the connection; 0 means "not yet assigned" (older files).
+    #[serde(default)]
+    id: usize,
     start_node_id: usize,
     start_node_type: NodeType,
     start_side: Side,
@@ -133,6 +488,28 @@ struct NodeConnection {
         deserialize_with = "ser_de::deserialize_color"
     )]
     color: egui::Color32,
+    #[serde(default)]
+    label: String,
+    // Undirected links are drawn without an arrowhead.
+    #[serde(default)]
+    undirected: bool,
+    // Pick the sides facing the other node every frame instead of keeping the stored ones.
+    #[serde(default)]
+    auto_sides: bool,
+    // Length of the arrowhead strokes and how far the curve bows out from the node sides,
+    // both in screen pixels.
+    #[serde(default = "default_arrow_head_size")]
+    arrow_head_size: f32,
+    #[serde(default = "default_offset_distance")]
+    offset_distance: f32,
+}
+
+fn default_arrow_head_size() -> f32 {
+    10.0
+}
+
+fn default_offset_distance() -> f32 {
+    50.0
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -151,6 +528,47 @@ struct NoteNode {
     text: String,
     is_dragging: bool,
     locked: bool,
+    #[serde(
+        default = "default_note_background",
+        serialize_with = "ser_de::serialize_color",
+        deserialize_with = "deserialize_note_background"
+    )]
+    bg_color: egui::Color32,
+    #[serde(
+        default = "default_note_text_color",
+        serialize_with = "ser_de::serialize_color",
+        deserialize_with = "deserialize_note_text_color"
+    )]
+    text_color: egui::Color32,
+    // Show the text as rendered markdown while the note is locked.
+    #[serde(default)]
+    render_markdown: bool,
+    // Topic labels, shown as chips above the node and usable as a filter.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+fn default_note_background() -> egui::Color32 {
+    NOTE_BACKGROUND
+}
+
+fn default_note_text_color() -> egui::Color32 {
+    NOTE_TEXT_COLOR
+}
+
+// Note colors were optional before, stored as null when left at the default.
+fn deserialize_note_background<'de, D>(deserializer: D) -> Result<egui::Color32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(ser_de::deserialize_color_opt(deserializer)?.unwrap_or(NOTE_BACKGROUND))
+}
+
+fn deserialize_note_text_color<'de, D>(deserializer: D) -> Result<egui::Color32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(ser_de::deserialize_color_opt(deserializer)?.unwrap_or(NOTE_TEXT_COLOR))
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -171,6 +589,169 @@ struct CodeNode {
     is_dragging: bool,
     locked: bool,
     line_offset: Option<usize>,
+    #[serde(default = "default_code_theme")]
+    theme: String,
+    // Custom background color overriding the theme's.
+    #[serde(
+        default,
+        serialize_with = "ser_de::serialize_color_opt",
+        deserialize_with = "ser_de::deserialize_color_opt"
+    )]
+    bg_color: Option<egui::Color32>,
+    // Follow the snippet in its file when the file changes on disk (see `sync_code_nodes`).
+    #[serde(default)]
+    sync: bool,
+    // Modification time of the file when it was last synced.
+    #[serde(skip)]
+    synced_modified: Option<std::time::SystemTime>,
+    // The snippet could no longer be found in its file.
+    #[serde(skip)]
+    sync_lost: bool,
+    // Name of the syntax to highlight with; `None` infers it from the file extension.
+    #[serde(default)]
+    language: Option<String>,
+    // Topic labels, shown as chips above the node and usable as a filter.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+fn default_code_theme() -> String {
+    CODE_THEMES[0].name.to_string()
+}
+
+// Where an image node's picture comes from. Only PNG images can be decoded.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+enum ImageSource {
+    // Path of the file, relative to the project root.
+    Path(String),
+    // The PNG file itself, base64 encoded into the project (e.g. a pasted screenshot).
+    Embedded(String),
+}
+
+impl ImageSource {
+    // The encoded image, read from its file or decoded from the project.
+    fn bytes(&self, project_root: Option<&std::path::Path>) -> io::Result<Vec<u8>> {
+        match self {
+            ImageSource::Path(path) => match project_root {
+                Some(root) => fs::read(root.join(path)),
+                None => fs::read(path),
+            },
+            ImageSource::Embedded(data) => base64::decode(data).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Embedded image data is corrupt")
+            }),
+        }
+    }
+
+    // Short description for the inspector and search results.
+    fn description(&self) -> &str {
+        match self {
+            ImageSource::Path(path) => path.as_str(),
+            ImageSource::Embedded(_) => "Pasted image",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ImageNode {
+    id: usize,
+    #[serde(
+        serialize_with = "ser_de::serialize_pos2",
+        deserialize_with = "ser_de::deserialize_pos2"
+    )]
+    position: egui::Pos2,
+    #[serde(
+        serialize_with = "ser_de::serialize_vec2",
+        deserialize_with = "ser_de::deserialize_vec2"
+    )]
+    size: egui::Vec2,
+    source: ImageSource,
+    is_dragging: bool,
+    // Locked images can't be moved or resized on their own.
+    locked: bool,
+}
+
+// A code or image node put on the system clipboard. Notes are copied as their plain text
+// instead, so they paste into other apps.
+#[derive(Serialize, Deserialize)]
+enum CopiedNode {
+    Code(CodeNode),
+    Image(ImageNode),
+}
+
+// Helper function: decode an encoded image into RGBA pixels.
+fn decode_image(bytes: &[u8]) -> io::Result<image::RgbaImage> {
+    image::load_from_memory(bytes)
+        .map(|picture| picture.to_rgba8())
+        .map_err(io::Error::other)
+}
+
+// Editor-like color theme for code nodes: background fill and default text color.
+struct CodeNodeTheme {
+    name: &'static str,
+    background: egui::Color32,
+    text: egui::Color32,
+    // Name of the bundled syntect theme used to highlight locked code.
+    syntax_theme: &'static str,
+}
+
+const CODE_THEMES: [CodeNodeTheme; 4] = [
+    CodeNodeTheme {
+        name: "One Dark",
+        background: egui::Color32::from_rgb(30, 35, 40),
+        text: egui::Color32::from_rgb(187, 192, 206),
+        syntax_theme: "base16-ocean.dark",
+    },
+    CodeNodeTheme {
+        name: "Solarized Dark",
+        background: egui::Color32::from_rgb(0, 43, 54),
+        text: egui::Color32::from_rgb(147, 161, 161),
+        syntax_theme: "Solarized (dark)",
+    },
+    CodeNodeTheme {
+        name: "Solarized Light",
+        background: egui::Color32::from_rgb(253, 246, 227),
+        text: egui::Color32::from_rgb(88, 110, 117),
+        syntax_theme: "Solarized (light)",
+    },
+    CodeNodeTheme {
+        name: "Monokai",
+        background: egui::Color32::from_rgb(39, 40, 34),
+        text: egui::Color32::from_rgb(248, 248, 242),
+        syntax_theme: "base16-mocha.dark",
+    },
+];
+
+// Look up a code theme by name, falling back to the default theme for unknown names.
+fn code_theme(name: &str) -> &'static CodeNodeTheme {
+    CODE_THEMES
+        .iter()
+        .find(|theme| theme.name == name)
+        .unwrap_or(&CODE_THEMES[0])
+}
+
+// Helper function: the color of a tag, picked from a hash of its text (FNV-1a) so it stays
+// the same across sessions.
+fn tag_color(tag: &str) -> egui::Color32 {
+    let hash = tag.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    let hue = (hash % 360) as f32 / 360.0;
+    egui::ecolor::Hsva::new(hue, 0.45, 0.85, 1.0).into()
+}
+
+// Helper function: draw a node's tags as chips sitting on top of its screen `rect`.
+fn paint_tag_chips(painter: &egui::Painter, rect: egui::Rect, tags: &[String], zoom: f32) {
+    let font_id = egui::FontId::proportional(5.0 * zoom);
+    let padding = egui::vec2(2.0, 1.0) * zoom;
+    let mut left = rect.left();
+    for tag in tags {
+        let galley = painter.layout_no_wrap(tag.clone(), font_id.clone(), egui::Color32::BLACK);
+        let size = galley.size() + 2.0 * padding;
+        let chip = egui::Rect::from_min_size(egui::pos2(left, rect.top() - size.y), size);
+        painter.rect_filled(chip, 2.0 * zoom, tag_color(tag));
+        painter.galley(chip.min + padding, galley, egui::Color32::BLACK);
+        left = chip.right() + padding.x;
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -186,15 +767,201 @@ struct Stroke {
     )]
     color: egui::Color32,
     thickness: f32,
+    #[serde(default)]
+    kind: StrokeKind,
+    // Width at each point, parallel to `points`, for strokes drawn with speed-dependent
+    // width. Empty means `thickness` everywhere.
+    #[serde(default)]
+    widths: Vec<f32>,
+}
+
+// How the background grid is drawn: full lines, a dot at each intersection, or not at all.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+enum GridStyle {
+    #[default]
+    Lines,
+    Dots,
+    None,
+}
+
+// Marker strokes are drawn over connections; highlighter strokes go under everything but
+// the grid.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+enum StrokeKind {
+    #[default]
+    Marker,
+    Highlighter,
+}
+
+impl Stroke {
+    // Bounding box of the stroke in canvas coordinates.
+    fn bounds(&self) -> egui::Rect {
+        egui::Rect::from_points(&self.points)
+    }
+
+    // Widest point of the stroke; speed-dependent strokes get wider than their thickness.
+    fn max_width(&self) -> f32 {
+        self.widths.iter().copied().fold(self.thickness, f32::max)
+    }
+
+    // Each segment of the stroke with its width, the mean of its two points' widths.
+    fn segments(&self) -> impl Iterator<Item = ([egui::Pos2; 2], f32)> + '_ {
+        self.points.windows(2).enumerate().map(|(i, window)| {
+            let width = match (self.widths.get(i), self.widths.get(i + 1)) {
+                (Some(a), Some(b)) => (a + b) / 2.0,
+                _ => self.thickness,
+            };
+            ([window[0], window[1]], width)
+        })
+    }
+
+    // Keep only the points (and their widths) for which `keep` returns true.
+    fn retain_points(&mut self, keep: impl Fn(&egui::Pos2) -> bool) {
+        if !self.widths.is_empty() {
+            self.widths = self
+                .points
+                .iter()
+                .zip(&self.widths)
+                .filter(|(point, _)| keep(point))
+                .map(|(_, width)| *width)
+                .collect();
+        }
+        self.points.retain(keep);
+    }
+}
+
+// A dimension line between two canvas points, labeled with its length.
+#[derive(Clone, Serialize, Deserialize)]
+struct Measurement {
+    #[serde(
+        serialize_with = "ser_de::serialize_pos2",
+        deserialize_with = "ser_de::deserialize_pos2"
+    )]
+    start: egui::Pos2,
+    #[serde(
+        serialize_with = "ser_de::serialize_pos2",
+        deserialize_with = "ser_de::deserialize_pos2"
+    )]
+    end: egui::Pos2,
+}
+
+impl Measurement {
+    fn length(&self) -> f32 {
+        self.start.distance(self.end)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum ShapeKind {
+    Line,
+    Rect,
+    Ellipse,
+}
+
+// A straight line between two canvas points, or an axis-aligned rectangle or ellipse with
+// them as opposite corners of its bounding box.
+#[derive(Clone, Serialize, Deserialize)]
+struct Shape {
+    kind: ShapeKind,
+    #[serde(
+        serialize_with = "ser_de::serialize_pos2",
+        deserialize_with = "ser_de::deserialize_pos2"
+    )]
+    start: egui::Pos2,
+    #[serde(
+        serialize_with = "ser_de::serialize_pos2",
+        deserialize_with = "ser_de::deserialize_pos2"
+    )]
+    end: egui::Pos2,
+    #[serde(
+        serialize_with = "ser_de::serialize_color",
+        deserialize_with = "ser_de::deserialize_color"
+    )]
+    color: egui::Color32,
+    thickness: f32,
+}
+
+impl Shape {
+    // Outline as a polyline in canvas coordinates (closed for rectangles and ellipses).
+    fn outline(&self) -> Vec<egui::Pos2> {
+        match self.kind {
+            ShapeKind::Line => vec![self.start, self.end],
+            ShapeKind::Rect => {
+                let rect = egui::Rect::from_two_pos(self.start, self.end);
+                vec![
+                    rect.left_top(),
+                    rect.right_top(),
+                    rect.right_bottom(),
+                    rect.left_bottom(),
+                    rect.left_top(),
+                ]
+            }
+            ShapeKind::Ellipse => {
+                let rect = egui::Rect::from_two_pos(self.start, self.end);
+                let radius = rect.size() / 2.0;
+                (0..=ELLIPSE_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f32 / ELLIPSE_SEGMENTS as f32 * std::f32::consts::TAU;
+                        rect.center() + egui::vec2(angle.cos(), angle.sin()) * radius
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// Helper function: move `end` so the shape from `start` is a square, circle or a line at a
+// multiple of 45°, as when dragging with Shift.
+fn constrain_shape_end(kind: ShapeKind, start: egui::Pos2, end: egui::Pos2) -> egui::Pos2 {
+    let delta = end - start;
+    match kind {
+        ShapeKind::Line => {
+            let step = std::f32::consts::FRAC_PI_4;
+            let angle = (delta.angle() / step).round() * step;
+            start + egui::Vec2::angled(angle) * delta.length()
+        }
+        ShapeKind::Rect | ShapeKind::Ellipse => {
+            let side = delta.x.abs().max(delta.y.abs());
+            start + egui::vec2(side.copysign(delta.x), side.copysign(delta.y))
+        }
+    }
+}
+
+// Helper function: end of a shape being drawn to the (already snapped) pointer position `pos`,
+// constrained as with Shift if `constrain` is set. A constrained end is snapped to the grid
+// spacing `snap` again; with `start` on the grid too, the shape stays square (or at 45°).
+fn shape_end(
+    kind: ShapeKind,
+    start: egui::Pos2,
+    pos: egui::Pos2,
+    constrain: bool,
+    snap: Option<f32>,
+) -> egui::Pos2 {
+    if !constrain {
+        return pos;
+    }
+    let end = constrain_shape_end(kind, start, pos);
+    snap.map_or(end, |spacing| snap_to_grid(end, spacing))
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 struct ProjectSnapshot {
     note_nodes: Vec<NoteNode>,
     code_nodes: Vec<CodeNode>,
+    #[serde(default)]
+    image_nodes: Vec<ImageNode>,
     connections: Vec<NodeConnection>,
     strokes: Vec<Stroke>,
-    zoom: f32,
+    #[serde(default)]
+    measurements: Vec<Measurement>,
+    #[serde(default)]
+    shapes: Vec<Shape>,
+    // Per-axis zoom; files from older versions store a single number.
+    #[serde(
+        serialize_with = "ser_de::serialize_vec2",
+        deserialize_with = "ser_de::deserialize_zoom"
+    )]
+    zoom: egui::Vec2,
     #[serde(
         serialize_with = "ser_de::serialize_vec2",
         deserialize_with = "ser_de::deserialize_vec2"
@@ -202,68 +969,380 @@ struct ProjectSnapshot {
     offset: egui::Vec2,
 }
 
+// Rendered geometry of a connection in screen coordinates.
+struct ConnectionCurve {
+    control1: egui::Pos2,
+    control2: egui::Pos2,
+    end: egui::Pos2,
+    points: Vec<egui::Pos2>,
+}
+
+impl ConnectionCurve {
+    // Point halfway along the curve, where labels and menus are anchored.
+    fn midpoint(&self) -> egui::Pos2 {
+        self.points[self.points.len() / 2]
+    }
+
+    // Point a quarter of the way along the curve, where its curvature handle sits.
+    fn offset_handle(&self) -> egui::Pos2 {
+        self.points[self.points.len() / 4]
+    }
+
+    // Direction the curve points in as it reaches `end`.
+    fn end_direction(&self) -> egui::Vec2 {
+        (self.end - self.control2).normalized()
+    }
+
+    // Outer points of the two arrowhead strokes, which both start at `end`.
+    fn arrow_head(&self, size: f32) -> (egui::Pos2, egui::Pos2) {
+        let last_segment_dir = self.end_direction();
+        let perp = egui::vec2(-last_segment_dir.y, last_segment_dir.x);
+        let arrow_left = self.end - last_segment_dir * size + perp * size * 0.5;
+        let arrow_right = self.end - last_segment_dir * size - perp * size * 0.5;
+        (arrow_left, arrow_right)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+// A message shown in the log panel.
+struct LogEntry {
+    time: chrono::DateTime<chrono::Local>,
+    level: LogLevel,
+    message: String,
+}
+
+// User settings, kept in eframe storage rather than in project files.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Preferences {
+    // Periodically save the session to eframe storage (see `App::save`).
+    autosave_enabled: bool,
+    autosave_interval_secs: u64,
+    // Double-click actions, each of which can be turned off (see `MyApp::handle_double_click`).
+    double_click_new_note: bool,
+    double_click_edit_node: bool,
+    double_click_edit_label: bool,
+    // Undo and redo snapshots kept (each) when saving a project file; `None` keeps them all.
+    max_saved_history: Option<usize>,
+    // Undo steps kept in memory. Every snapshot clones the whole canvas, so this bounds
+    // how much a long session can grow; the oldest steps are dropped first.
+    max_undo_depth: usize,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            autosave_enabled: true,
+            autosave_interval_secs: 30,
+            double_click_new_note: true,
+            double_click_edit_node: true,
+            double_click_edit_label: true,
+            max_saved_history: None,
+            max_undo_depth: 100,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct ProjectHistory {
     undo_stack: Vec<ProjectSnapshot>,
     redo_stack: Vec<ProjectSnapshot>,
     current: ProjectSnapshot,
+    // Folder code node file paths are relative to.
+    #[serde(default)]
+    project_root: Option<std::path::PathBuf>,
+    #[serde(default = "default_grid_spacing")]
+    grid_spacing: f32,
+    #[serde(default)]
+    grid_style: GridStyle,
 }
 
-struct MyApp {
-    zoom: f32,
-    offset: egui::Vec2,
-    dragging: bool,
-    drag_start: egui::Pos2,
-    tools_open: bool,
+fn default_grid_spacing() -> f32 {
+    GRID_SPACING
+}
+
+impl ProjectHistory {
+    // Keep only the `max` snapshots closest to the current one on each stack.
+    fn truncate(&mut self, max: usize) {
+        for stack in [&mut self.undo_stack, &mut self.redo_stack] {
+            let excess = stack.len().saturating_sub(max);
+            stack.drain(..excess);
+        }
+    }
+}
+
+// Only the current snapshot of a project file. The undo/redo stacks in the file are skipped
+// while parsing instead of being loaded.
+#[derive(Deserialize)]
+struct ProjectCurrent {
+    current: ProjectSnapshot,
+    #[serde(default)]
+    project_root: Option<std::path::PathBuf>,
+    #[serde(default = "default_grid_spacing")]
+    grid_spacing: f32,
+    #[serde(default)]
+    grid_style: GridStyle,
+}
+
+struct MyApp {
+    // Canvas to screen scale, per axis. Uniform unless stretched with Alt+scroll.
+    zoom: egui::Vec2,
+    offset: egui::Vec2,
+    dragging: bool,
+    drag_start: egui::Pos2,
+    // Momentum of the canvas after a pan drag is released (screen pixels per second).
+    pan_inertia: bool,
+    pan_velocity: egui::Vec2,
+    tools_open: bool,
+    show_origin: bool,
+    // Show the canvas coordinate under the pointer next to it.
+    show_cursor_coords: bool,
+    show_minimap: bool,
+    highlight_orphans: bool,
+    // Tags picked in the Tools bar; nodes with none of them are dimmed.
+    tag_filter: HashSet<String>,
+    // Text typed in a node's Tags menu, added as a tag on Enter.
+    new_tag: String,
+    // Text typed in the Tools bar search field; nodes containing it are outlined.
+    search_query: String,
+    save_thumbnail_enabled: bool,
+    // Pixels per canvas unit for PNG export, independent of the current zoom.
+    export_scale: f32,
     next_note_id: usize,
     note_nodes: Vec<NoteNode>,
     code_nodes: Vec<CodeNode>,
+    image_nodes: Vec<ImageNode>,
+    // Textures of image nodes by node id, with the source they were loaded from. `None` if
+    // the source couldn't be read or decoded, so it isn't retried every frame.
+    image_textures: HashMap<usize, (ImageSource, Option<egui::TextureHandle>)>,
     connections: Vec<NodeConnection>,
-    marker_active: bool,
-    eraser_active: bool,
+    active_tool: Tool,
     current_stroke: Option<Stroke>,
+    // Whether the finished marker stroke has been recorded, so it is recorded only once after
+    // the pointer is released.
+    marker_stroke_recorded: bool,
+    // Whether the current erase gesture has been recorded (before its first change).
+    eraser_state_recorded: bool,
+    // Erase whole strokes on contact instead of only the points under the eraser.
+    stroke_eraser: bool,
+    // Color and thickness of new marker strokes.
+    marker_color: egui::Color32,
+    marker_thickness: f32,
+    // Color (translucent) and thickness of new highlighter strokes.
+    highlighter_color: egui::Color32,
+    highlighter_thickness: f32,
+    // Draw marker strokes thinner where the pointer moves fast (see `speed_width`).
+    marker_speed_width: bool,
+    // How much marker and highlighter strokes are smoothed when finished, from 0 (raw) to 1.
+    stroke_smoothing: f32,
     strokes: Vec<Stroke>,
+    measurements: Vec<Measurement>,
+    // First point of a measurement being placed with the Measure tool.
+    measure_start: Option<egui::Pos2>,
+    shapes: Vec<Shape>,
+    // Line or rectangle being dragged out with a shape tool.
+    current_shape: Option<Shape>,
+    snap_to_grid: bool,
+    // Distance between grid lines (and snap points) in canvas units, saved with the project.
+    grid_spacing: f32,
+    grid_style: GridStyle,
     project_root: Option<std::path::PathBuf>,
+    selected_strokes: Vec<usize>,
+    // Canvas corner where a drag-select region started with the Select tool.
+    region_start: Option<egui::Pos2>,
     // Connection-related fields
-    arrow_connection_active: bool,
     connection_start: Option<(usize, NodeType, Side)>,
+    next_connection_id: usize,
+    selected_connections: HashSet<usize>,
+    // Arrow the canvas context menu was opened on.
+    context_connection: Option<usize>,
+    // Canvas point the empty-canvas context menu was opened at.
+    context_canvas_pos: Option<egui::Pos2>,
+    connection_hit_radius: f32,
     // Undo/Redo stacks
     undo_stack: Vec<ProjectSnapshot>,
     redo_stack: Vec<ProjectSnapshot>,
-    // Node selection (for floating menus)
-    selected_node: Option<usize>,
+    // Node whose floating menu is open, by type and id so it survives reordering and
+    // deleting other nodes.
+    selected_node: Option<(NodeType, usize)>,
+    // Nodes picked with the Select tool (Shift+click adds more).
+    selected_nodes: Vec<(NodeType, usize)>,
+    // Pending text edit: state before typing started and time of the last keystroke.
+    text_edit_start: Option<ProjectSnapshot>,
+    last_text_change: Option<Instant>,
+    // Last change of a coalesced continuous edit (see `record_state_coalesced`).
+    last_coalesced_edit: Option<Instant>,
+    // State from before the current arrow key nudging, recorded once the keys are released.
+    nudge_start: Option<ProjectSnapshot>,
+    // Session found in eframe storage at startup, waiting for the user to resume or discard it.
+    pending_session: Option<ProjectHistory>,
+    // File the board was last opened from or saved to.
+    current_project_path: Option<std::path::PathBuf>,
+    // Recovery file newer than the opened project, waiting for the user to recover or discard it.
+    pending_recovery: Option<std::path::PathBuf>,
+    // Whether the board changed since it was last saved, created or opened.
+    dirty: bool,
+    // Action waiting for the user to save or discard unsaved changes.
+    confirm_discard: Option<FileAction>,
+    preferences: Preferences,
+    show_preferences: bool,
+    show_shortcuts: bool,
+    // Node whose text editor should take focus on the next frame, after a double-click.
+    edit_request: Option<(NodeType, usize)>,
+    // Connection whose label is being edited, with the text typed so far.
+    label_edit: Option<(usize, String)>,
+    last_autosave: Option<Instant>,
+    clipboard: Option<arboard::Clipboard>,
+    last_code_sync: Option<Instant>,
+    // Diagnostics for users who never see stderr, newest last (see `log`).
+    log: VecDeque<LogEntry>,
+    show_log: bool,
+    // Short notifications in the bottom right corner, with the time they were shown.
+    toasts: Vec<(String, Instant)>,
 }
 
 impl Default for MyApp {
     fn default() -> Self {
         Self {
-            zoom: 2.0,
+            zoom: egui::Vec2::splat(2.0),
             offset: egui::Vec2::ZERO,
             dragging: false,
             drag_start: egui::Pos2::ZERO,
+            pan_inertia: false,
+            pan_velocity: egui::Vec2::ZERO,
             tools_open: false,
+            show_origin: false,
+            show_cursor_coords: false,
+            show_minimap: true,
+            highlight_orphans: false,
+            tag_filter: HashSet::new(),
+            new_tag: String::new(),
+            search_query: String::new(),
+            save_thumbnail_enabled: false,
+            export_scale: 2.0,
             next_note_id: 1,
             note_nodes: Vec::new(),
             code_nodes: Vec::new(),
+            image_nodes: Vec::new(),
+            image_textures: HashMap::new(),
             connections: Vec::new(),
-            marker_active: false,
-            eraser_active: false,
+            active_tool: Tool::None,
             current_stroke: None,
+            marker_stroke_recorded: false,
+            eraser_state_recorded: false,
+            stroke_eraser: false,
+            marker_color: egui::Color32::from_rgb(187, 192, 206),
+            marker_thickness: 2.0,
+            highlighter_color: egui::Color32::from_rgba_unmultiplied(229, 192, 123, 80),
+            highlighter_thickness: 12.0,
+            marker_speed_width: false,
+            stroke_smoothing: 0.4,
             strokes: Vec::new(),
+            measurements: Vec::new(),
+            measure_start: None,
+            shapes: Vec::new(),
+            current_shape: None,
+            snap_to_grid: false,
+            grid_spacing: GRID_SPACING,
+            grid_style: GridStyle::Lines,
             project_root: None,
-            arrow_connection_active: false,
+            selected_strokes: Vec::new(),
+            region_start: None,
             connection_start: None,
+            next_connection_id: 1,
+            selected_connections: HashSet::new(),
+            context_connection: None,
+            context_canvas_pos: None,
+            connection_hit_radius: CONNECTION_HIT_RADIUS,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
             selected_node: None,
+            selected_nodes: Vec::new(),
+            text_edit_start: None,
+            last_text_change: None,
+            last_coalesced_edit: None,
+            nudge_start: None,
+            pending_session: None,
+            current_project_path: None,
+            pending_recovery: None,
+            dirty: false,
+            confirm_discard: None,
+            preferences: Preferences::default(),
+            show_preferences: false,
+            show_shortcuts: false,
+            edit_request: None,
+            label_edit: None,
+            last_autosave: None,
+            clipboard: None,
+            last_code_sync: None,
+            log: VecDeque::new(),
+            show_log: false,
+            toasts: Vec::new(),
         }
     }
 }
 
 impl MyApp {
+    // `project_path` is a project file to open at startup, e.g. from the command line.
+    fn new(cc: &eframe::CreationContext<'_>, project_path: Option<String>) -> Self {
+        let pending_session = cc
+            .storage
+            .and_then(|storage| storage.get_string(SESSION_KEY))
+            .and_then(|json| serde_json::from_str::<ProjectHistory>(&json).ok())
+            .filter(|session| {
+                let current = &session.current;
+                !current.note_nodes.is_empty()
+                    || !current.code_nodes.is_empty()
+                    || !current.image_nodes.is_empty()
+                    || !current.strokes.is_empty()
+                    || !current.measurements.is_empty()
+                    || !current.shapes.is_empty()
+            });
+        let preferences = cc
+            .storage
+            .and_then(|storage| storage.get_string(PREFERENCES_KEY))
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let tools_open = cc
+            .storage
+            .and_then(|storage| storage.get_string(TOOLS_OPEN_KEY))
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(false);
+        let mut app = Self {
+            pending_session,
+            preferences,
+            tools_open,
+            ..Self::default()
+        };
+        if let Some(path) = project_path {
+            match app.load_project(&path) {
+                Ok(()) => {
+                    // The opened project replaces the stored session.
+                    app.pending_session = None;
+                    app.log_info(format!("Loaded {}", path));
+                    app.opened_project(path.into());
+                }
+                Err(e) => {
+                    app.log_error(format!("Load error: {}: {}", path, e));
+                    app.show_toast(format!("Could not open {}", path));
+                }
+            }
+        }
+        app
+    }
+
     // Save entire project history (if desired)
     fn save_project(&self, file_path: &str) -> io::Result<()> {
-        let history = self.project_history();
+        let mut history = self.project_history();
+        if let Some(max) = self.preferences.max_saved_history {
+            history.truncate(max);
+        }
         let json = serde_json::to_string_pretty(&history)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         let mut file = File::create(file_path)?;
@@ -271,29 +1350,268 @@ impl MyApp {
         Ok(())
     }
 
+    // Write the project to another file, e.g. to fork a board for an experiment. Nothing
+    // about the board being edited changes.
+    fn save_copy(&mut self) {
+        let Some(path) = self.dialog_path(
+            rfd::FileDialog::new()
+                .set_file_name("copy.json")
+                .save_file(),
+        ) else {
+            return;
+        };
+        match self.save_project(&path) {
+            Ok(()) => {
+                self.log_info(format!("Saved a copy to {}", path));
+                self.show_toast(format!("Saved a copy to {}", path));
+            }
+            Err(e) => self.log_error(format!("Save error: {}", e)),
+        }
+    }
+
     // Load project history and restore state.
     fn load_project(&mut self, file_path: &str) -> io::Result<()> {
         let json = std::fs::read_to_string(file_path)?;
         let history: ProjectHistory =
             serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        // Node ids of another project may match cached textures.
+        self.image_textures.clear();
         self.undo_stack = history.undo_stack;
         self.redo_stack = history.redo_stack;
         self.restore_snapshot(history.current);
+        self.restore_project_root(history.project_root);
+        self.restore_grid(history.grid_spacing, history.grid_style);
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Use the project root stored in a project file, unless it no longer exists. Files without
+    // one (older files) keep the current root.
+    fn restore_project_root(&mut self, project_root: Option<std::path::PathBuf>) {
+        let Some(root) = project_root else {
+            return;
+        };
+        if root.is_dir() {
+            self.project_root = Some(root);
+        } else {
+            self.log(
+                LogLevel::Warning,
+                format!(
+                    "Project folder {} not found; choose it again",
+                    root.display()
+                ),
+            );
+            self.project_root = None;
+        }
+    }
+    // Use the grid settings stored in a project file, falling back to the default spacing if
+    // the stored one is unusable.
+    fn restore_grid(&mut self, spacing: f32, style: GridStyle) {
+        self.grid_spacing = if spacing.is_finite() && spacing >= 1.0 {
+            spacing
+        } else {
+            GRID_SPACING
+        };
+        self.grid_style = style;
+    }
+
+    // Add a message to the log panel (and stderr), dropping the oldest beyond `MAX_LOG_ENTRIES`.
+    fn log(&mut self, level: LogLevel, message: impl Into<String>) {
+        let message = message.into();
+        eprintln!("{}", message);
+        if self.log.len() == MAX_LOG_ENTRIES {
+            self.log.pop_front();
+        }
+        self.log.push_back(LogEntry {
+            time: chrono::Local::now(),
+            level,
+            message,
+        });
+    }
+    fn log_info(&mut self, message: impl Into<String>) {
+        self.log(LogLevel::Info, message);
+    }
+    fn log_error(&mut self, message: impl Into<String>) {
+        self.log(LogLevel::Error, message);
+    }
+
+    // Path picked in a file dialog as a string, or `None` if the dialog was cancelled. Paths
+    // that aren't valid UTF-8 are logged and give `None` too.
+    fn dialog_path(&mut self, path: Option<std::path::PathBuf>) -> Option<String> {
+        let path = path?;
+        match path.to_str() {
+            Some(path) => Some(path.to_owned()),
+            None => {
+                self.log_error(format!("Unsupported file name: {}", path.display()));
+                None
+            }
+        }
+    }
+
+    // Show a short notification for `TOAST_DURATION`.
+    fn show_toast(&mut self, message: impl Into<String>) {
+        self.toasts.push((message.into(), Instant::now()));
+    }
+
+    // Like `load_project`, but starts with empty undo/redo stacks.
+    fn load_project_without_history(&mut self, file_path: &str) -> io::Result<()> {
+        let json = std::fs::read_to_string(file_path)?;
+        let project: ProjectCurrent = serde_json::from_str(&json).map_err(io::Error::other)?;
+        self.image_textures.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.restore_snapshot(project.current);
+        self.restore_project_root(project.project_root);
+        self.restore_grid(project.grid_spacing, project.grid_style);
+        self.dirty = false;
         Ok(())
     }
+
+    // Start an empty board with fresh history.
+    fn new_project(&mut self) {
+        self.note_nodes.clear();
+        self.code_nodes.clear();
+        self.image_nodes.clear();
+        self.image_textures.clear();
+        self.connections.clear();
+        self.strokes.clear();
+        self.measurements.clear();
+        self.shapes.clear();
+        self.measure_start = None;
+        self.active_tool = Tool::None;
+        self.connection_start = None;
+        self.selected_node = None;
+        self.selected_strokes.clear();
+        self.selected_nodes.clear();
+        self.selected_connections.clear();
+        self.zoom = egui::Vec2::splat(2.0);
+        self.offset = egui::Vec2::ZERO;
+        self.grid_spacing = GRID_SPACING;
+        self.grid_style = GridStyle::Lines;
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.record_state();
+        self.dirty = false;
+        self.current_project_path = None;
+        self.pending_recovery = None;
+    }
+
+    // Ask for a project file and open it, with or without its undo history.
+    fn open_project_dialog(&mut self, with_history: bool) {
+        let Some(path) = self.dialog_path(rfd::FileDialog::new().pick_file()) else {
+            return;
+        };
+        let path = path.as_str();
+        let result = if with_history {
+            self.load_project(path)
+        } else {
+            self.load_project_without_history(path)
+        };
+        match (result, with_history) {
+            (Ok(()), true) => self.log_info(format!("Loaded {}", path)),
+            (Ok(()), false) => self.log_info(format!("Loaded {} without history", path)),
+            (Err(e), _) => {
+                self.log_error(format!("Load error: {}", e));
+                return;
+            }
+        }
+        self.opened_project(path.into());
+    }
+
+    // Remember the project just opened, and offer its recovery file if it is newer.
+    fn opened_project(&mut self, path: std::path::PathBuf) {
+        self.pending_recovery = newer_autosave(&path);
+        self.current_project_path = Some(path);
+    }
+
+    // Write the recovery file of the open project, if it has unsaved changes.
+    fn write_autosave(&mut self) {
+        let Some(path) = &self.current_project_path else {
+            return;
+        };
+        if !self.dirty || self.pending_recovery.is_some() {
+            return;
+        }
+        let autosave = autosave_path(path);
+        if let Err(e) = self.save_project(&autosave.to_string_lossy()) {
+            self.log_error(format!("Autosave error: {}: {}", autosave.display(), e));
+        }
+    }
+
+    // Delete the recovery file of the open project, once its changes are saved or discarded.
+    fn remove_autosave(&mut self) {
+        let Some(path) = &self.current_project_path else {
+            return;
+        };
+        let autosave = autosave_path(path);
+        match fs::remove_file(&autosave) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => self.log_error(format!("Autosave error: {}: {}", autosave.display(), e)),
+        }
+    }
+
+    // Ask where to save the project and save it there, with a thumbnail if enabled.
+    // Returns whether the project was saved.
+    fn save_project_dialog(&mut self) -> bool {
+        let Some(path) = self.dialog_path(rfd::FileDialog::new().save_file()) else {
+            return false;
+        };
+        let path = path.as_str();
+        if let Err(e) = self.save_project(path) {
+            self.log_error(format!("Save error: {}", e));
+            return false;
+        }
+        self.log_info(format!("Saved {}", path));
+        self.dirty = false;
+        // The changes are saved; drop the recovery files at the old and the new location.
+        self.remove_autosave();
+        self.current_project_path = Some(path.into());
+        self.pending_recovery = None;
+        self.remove_autosave();
+        if self.save_thumbnail_enabled {
+            if let Err(e) = self.save_thumbnail(path) {
+                self.log_error(format!("Thumbnail error: {}", e));
+            }
+        }
+        true
+    }
+
+    // Run an action that replaces the board, asking first if there are unsaved changes.
+    fn request_file_action(&mut self, action: FileAction) {
+        if self.dirty {
+            self.confirm_discard = Some(action);
+        } else {
+            self.run_file_action(action);
+        }
+    }
+
+    fn run_file_action(&mut self, action: FileAction) {
+        match action {
+            FileAction::New => self.new_project(),
+            FileAction::Open => self.open_project_dialog(true),
+            FileAction::OpenWithoutHistory => self.open_project_dialog(false),
+        }
+    }
     fn project_history(&self) -> ProjectHistory {
         ProjectHistory {
             undo_stack: self.undo_stack.clone(),
             redo_stack: self.redo_stack.clone(),
             current: self.take_snapshot(),
+            project_root: self.project_root.clone(),
+            grid_spacing: self.grid_spacing,
+            grid_style: self.grid_style,
         }
     }
     fn take_snapshot(&self) -> ProjectSnapshot {
         ProjectSnapshot {
             note_nodes: self.note_nodes.clone(),
             code_nodes: self.code_nodes.clone(),
+            image_nodes: self.image_nodes.clone(),
             connections: self.connections.clone(),
             strokes: self.strokes.clone(),
+            measurements: self.measurements.clone(),
+            shapes: self.shapes.clone(),
             zoom: self.zoom,
             offset: self.offset,
         }
@@ -302,329 +1620,3420 @@ impl MyApp {
     fn restore_snapshot(&mut self, snapshot: ProjectSnapshot) {
         self.note_nodes = snapshot.note_nodes;
         self.code_nodes = snapshot.code_nodes;
+        self.image_nodes = snapshot.image_nodes;
         self.connections = snapshot.connections;
         self.strokes = snapshot.strokes;
-        self.zoom = snapshot.zoom;
-        self.offset = snapshot.offset;
+        self.measurements = snapshot.measurements;
+        self.shapes = snapshot.shapes;
+        // Guard against a broken view in the file: zoom is a divisor for every transform.
+        self.zoom = if snapshot.zoom.is_finite() {
+            snapshot
+                .zoom
+                .clamp(egui::Vec2::splat(0.4), egui::Vec2::splat(4.0))
+        } else {
+            egui::Vec2::splat(2.0)
+        };
+        self.offset = if snapshot.offset.is_finite() {
+            snapshot.offset
+        } else {
+            egui::Vec2::ZERO
+        };
+        // Node ids are shared by all node types; keep new ones unique.
+        let max_node_id = self
+            .note_nodes
+            .iter()
+            .map(|n| n.id)
+            .chain(self.code_nodes.iter().map(|n| n.id))
+            .chain(self.image_nodes.iter().map(|n| n.id))
+            .max()
+            .unwrap_or(0);
+        self.next_note_id = self.next_note_id.max(max_node_id + 1);
+        self.assign_connection_ids();
+        // Stroke selection is by index, which may now point at a different stroke.
+        self.selected_strokes.clear();
+        self.selected_node = self
+            .selected_node
+            .filter(|&(node_type, id)| self.node_rect(node_type, id).is_some());
     }
 
-    fn record_state(&mut self) {
-        self.undo_stack.push(self.take_snapshot());
-        self.redo_stack.clear();
+    // Restore an undo or redo snapshot but keep the current view: panning and zooming aren't
+    // recorded as edits, so undoing one shouldn't move the view. Snapshots still carry the view
+    // for saving and loading.
+    fn restore_history_snapshot(&mut self, snapshot: ProjectSnapshot) {
+        let (zoom, offset) = (self.zoom, self.offset);
+        self.restore_snapshot(snapshot);
+        self.zoom = zoom;
+        self.offset = offset;
     }
 
-    fn undo(&mut self) {
-        if let Some(snapshot) = self.undo_stack.pop() {
-            self.redo_stack.push(self.take_snapshot());
-            self.restore_snapshot(snapshot);
-        }
+    // Screen position of a canvas point under the current view.
+    fn to_screen(&self, canvas_pos: egui::Pos2) -> egui::Pos2 {
+        (canvas_pos.to_vec2() * self.zoom).to_pos2() + self.offset
     }
 
-    fn redo(&mut self) {
-        if let Some(snapshot) = self.redo_stack.pop() {
-            self.undo_stack.push(self.take_snapshot());
-            self.restore_snapshot(snapshot);
+    // Canvas point shown at a screen position.
+    fn to_canvas(&self, screen_pos: egui::Pos2) -> egui::Pos2 {
+        ((screen_pos - self.offset).to_vec2() / self.zoom).to_pos2()
+    }
+
+    // Switch to `tool`, or back to no tool if it's already active.
+    fn toggle_tool(&mut self, tool: Tool) {
+        self.active_tool = if self.active_tool == tool {
+            Tool::None
+        } else {
+            tool
+        };
+        if self.active_tool != Tool::Arrow {
+            self.connection_start = None;
+        }
+        if self.active_tool != Tool::Measure {
+            self.measure_start = None;
         }
     }
-}
 
-fn main() -> eframe::Result<()> {
-    let options = eframe::NativeOptions::default();
-    eframe::run_native(
-        "CnF-Infinity",
-        options,
-        Box::new(|_cc| Ok(Box::new(MyApp::default()))),
-    )
-}
+    // Topmost entity under a screen position, by priority: node > connection > stroke.
+    // `curves` are the on-screen connection curves, in the same order as `connections`.
+    fn hit_test(&self, pos: egui::Pos2, curves: &[Option<ConnectionCurve>]) -> Option<CanvasHit> {
+        let canvas_pos = self.to_canvas(pos);
+        // Code nodes are drawn over notes, notes over images, and later nodes over earlier ones.
+        let code_hit = self
+            .code_nodes
+            .iter()
+            .rev()
+            .find(|n| egui::Rect::from_min_size(n.position, n.size).contains(canvas_pos))
+            .map(|n| CanvasHit::Node(NodeType::Code, n.id));
+        let note_hit = || {
+            self.note_nodes
+                .iter()
+                .rev()
+                .find(|n| egui::Rect::from_min_size(n.position, n.size).contains(canvas_pos))
+                .map(|n| CanvasHit::Node(NodeType::Note, n.id))
+        };
+        let image_hit = || {
+            self.image_nodes
+                .iter()
+                .rev()
+                .find(|n| egui::Rect::from_min_size(n.position, n.size).contains(canvas_pos))
+                .map(|n| CanvasHit::Node(NodeType::Image, n.id))
+        };
+        let connection_hit = || self.connection_at(pos, curves).map(CanvasHit::Connection);
+        let stroke_hit = || {
+            self.strokes
+                .iter()
+                .enumerate()
+                .map(|(index, stroke)| {
+                    let points: Vec<egui::Pos2> =
+                        stroke.points.iter().map(|p| self.to_screen(*p)).collect();
+                    let distance = distance_to_polyline(&points, pos)
+                        - stroke.thickness * self.zoom.min_elem() * 0.5;
+                    (index, distance)
+                })
+                .filter(|(_, distance)| *distance <= self.connection_hit_radius)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(index, _)| CanvasHit::Stroke(index))
+        };
+        code_hit
+            .or_else(note_hit)
+            .or_else(image_hit)
+            .or_else(connection_hit)
+            .or_else(stroke_hit)
+    }
 
-fn compute_cubic_bezier_points(
-    p0: egui::Pos2,
-    p1: egui::Pos2,
-    p2: egui::Pos2,
-    p3: egui::Pos2,
-    segments: usize,
-) -> Vec<egui::Pos2> {
-    let mut points = Vec::with_capacity(segments + 1);
-    for i in 0..=segments {
-        let t = i as f32 / segments as f32;
-        let one_minus_t = 1.0 - t;
-        let a = one_minus_t.powi(3);
-        let b = 3.0 * t * one_minus_t.powi(2);
-        let c = 3.0 * t.powi(2) * one_minus_t;
-        let d = t.powi(3);
-        let x = a * p0.x + b * p1.x + c * p2.x + d * p3.x;
-        let y = a * p0.y + b * p1.y + c * p2.y + d * p3.y;
-        points.push(egui::pos2(x, y));
+    // Single dispatch for double-clicks on the canvas. Only the topmost entity under the
+    // cursor (see `hit_test`) gets an action, so the actions never overlap:
+    // node > connection > stroke > empty canvas. Strokes have no double-click action.
+    fn handle_double_click(&mut self, pos: egui::Pos2, curves: &[Option<ConnectionCurve>]) {
+        match self.hit_test(pos, curves) {
+            Some(CanvasHit::Node(node_type, id)) if self.preferences.double_click_edit_node => {
+                // A locked note or code node is unlocked first, as one undo step. Escape in
+                // its text field locks it again.
+                let locked = match node_type {
+                    NodeType::Note => self.note_nodes.iter().any(|n| n.id == id && n.locked),
+                    NodeType::Code => self.code_nodes.iter().any(|n| n.id == id && n.locked),
+                    // Images have nothing to edit in place, so open their options instead.
+                    NodeType::Image => {
+                        self.selected_node = Some((node_type, id));
+                        return;
+                    }
+                };
+                if locked {
+                    self.record_state();
+                    match node_type {
+                        NodeType::Note => {
+                            if let Some(note) = self.note_nodes.iter_mut().find(|n| n.id == id) {
+                                note.locked = false;
+                            }
+                        }
+                        NodeType::Code => {
+                            if let Some(node) = self.code_nodes.iter_mut().find(|n| n.id == id) {
+                                node.locked = false;
+                            }
+                        }
+                        NodeType::Image => {}
+                    }
+                }
+                self.edit_request = Some((node_type, id));
+            }
+            Some(CanvasHit::Connection(id)) if self.preferences.double_click_edit_label => {
+                self.label_edit = self
+                    .connections
+                    .iter()
+                    .find(|c| c.id == id)
+                    .map(|c| (id, c.label.clone()));
+            }
+            None if self.preferences.double_click_new_note => {
+                let id = self.add_note_at(self.to_canvas(pos));
+                self.edit_request = Some((NodeType::Note, id));
+            }
+            _ => {}
+        }
     }
-    points
-}
 
-// Helper function: returns the outward normal for a given side.
-fn side_normal(side: Side) -> egui::Vec2 {
-    match side {
-        Side::Top => egui::vec2(0.0, -1.0),
-        Side::Bottom => egui::vec2(0.0, 1.0),
-        Side::Left => egui::vec2(-1.0, 0.0),
-        Side::Right => egui::vec2(1.0, 0.0),
+    // Clear every selection, which also closes their floating menus.
+    fn deselect_all(&mut self) {
+        self.selected_node = None;
+        self.selected_nodes.clear();
+        self.selected_connections.clear();
+        self.selected_strokes.clear();
     }
-}
 
-// Helper function: compute a connection point along a node's side.
-// If multiple arrows come from the same side, they are evenly distributed.
-fn connection_point(
-    node_pos: egui::Pos2,
-    node_size: egui::Vec2,
-    side: Side,
-    arrow_index: usize,
-    total: usize,
-) -> egui::Pos2 {
-    match side {
-        Side::Top => {
-            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
-            egui::pos2(node_pos.x + node_size.x * fraction, node_pos.y)
+    // Lock or unlock every node as one undo step. Code nodes that get locked have their
+    // line numbers located the same way as with their own Lock button.
+    fn set_all_locked(&mut self, locked: bool) {
+        let changes = self.note_nodes.iter().any(|n| n.locked != locked)
+            || self.code_nodes.iter().any(|n| n.locked != locked)
+            || self.image_nodes.iter().any(|n| n.locked != locked);
+        if !changes {
+            return;
         }
-        Side::Bottom => {
-            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
-            egui::pos2(
-                node_pos.x + node_size.x * fraction,
-                node_pos.y + node_size.y,
-            )
+        self.record_state();
+        for note in &mut self.note_nodes {
+            note.locked = locked;
         }
-        Side::Left => {
-            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
-            egui::pos2(node_pos.x, node_pos.y + node_size.y * fraction)
+        for node in &mut self.image_nodes {
+            node.locked = locked;
         }
-        Side::Right => {
-            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
-            egui::pos2(
-                node_pos.x + node_size.x,
-                node_pos.y + node_size.y * fraction,
-            )
+        let project_root = self.project_root.clone();
+        for node in self.code_nodes.iter_mut().filter(|n| n.locked != locked) {
+            if locked {
+                lock_code_node(node, project_root.as_deref());
+            } else {
+                node.locked = false;
+            }
         }
     }
-}
 
-// Helper function: given the list of connections, determine the index of the current connection
-// (i.e. its order among all arrows originating from the same node and side).
-fn get_arrow_index(
-    connections: &[NodeConnection],
-    node_id: usize,
-    side: Side,
-    current: &NodeConnection,
-) -> (usize, usize) {
-    let mut count = 0;
-    let mut index = 0;
-    for conn in connections {
-        if conn.start_node_id == node_id && conn.start_side == side {
-            if std::ptr::eq(conn, current) {
-                index = count;
+    // Id of the connection closest to a screen position, if it is within the hit radius.
+    fn connection_at(&self, pos: egui::Pos2, curves: &[Option<ConnectionCurve>]) -> Option<usize> {
+        self.connections
+            .iter()
+            .zip(curves)
+            .filter_map(|(conn, curve)| {
+                Some((conn.id, distance_to_polyline(&curve.as_ref()?.points, pos)))
+            })
+            .filter(|(_, distance)| *distance <= self.connection_hit_radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id)
+    }
+
+    // Give connections without an id (from older files) a fresh one and make sure
+    // `next_connection_id` stays ahead of every id in use.
+    fn assign_connection_ids(&mut self) {
+        let max_id = self.connections.iter().map(|c| c.id).max().unwrap_or(0);
+        self.next_connection_id = self.next_connection_id.max(max_id + 1);
+        for conn in &mut self.connections {
+            if conn.id == 0 {
+                conn.id = self.next_connection_id;
+                self.next_connection_id += 1;
             }
-            count += 1;
         }
     }
-    (index, count)
-}
 
-impl App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
-        ctx.set_visuals(egui::Visuals {
-            code_bg_color: egui::Color32::from_rgb(32, 37, 43),
-            panel_fill: egui::Color32::from_rgb(40, 44, 52),
-            window_fill: egui::Color32::from_rgb(40, 44, 52),
-            faint_bg_color: egui::Color32::from_rgb(40, 44, 52),
-            extreme_bg_color: egui::Color32::from_rgb(40, 44, 52),
-            ..Default::default()
-        });
-        // Canvas View
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let response = ui.interact(
-                ui.max_rect(),
-                ui.id(),
-                if !self.arrow_connection_active {
-                    egui::Sense::drag()
-                } else {
-                    egui::Sense::empty()
-                },
-            );
+    fn record_state(&mut self) {
+        self.commit_text_edit();
+        let snapshot = self.take_snapshot();
+        self.push_undo(snapshot);
+        self.redo_stack.clear();
+    }
 
-            // Grid Drawing
-            let spacing = (25.0 * self.zoom).max(1.0);
-            let grid_color = egui::Color32::from_gray(60);
-            let stroke = egui::Stroke::new(1.0, grid_color);
-            let bounds = ui.clip_rect();
-            let top_left = bounds.left_top() - self.offset;
-            let bottom_right = bounds.right_bottom() - self.offset;
-            let start_x = (top_left.x / spacing).floor() * spacing;
-            let end_x = (bottom_right.x / spacing).ceil() * spacing;
-            let start_y = (top_left.y / spacing).floor() * spacing;
-            let end_y = (bottom_right.y / spacing).ceil() * spacing;
-            let painter = ui.painter_at(bounds);
-            for x in (start_x as i32..=end_x as i32).step_by(spacing as usize) {
-                let x = x as f32;
-                painter.line_segment(
-                    [
-                        egui::pos2(x, top_left.y) + self.offset,
-                        egui::pos2(x, bottom_right.y) + self.offset,
-                    ],
-                    stroke,
-                );
-            }
-            for y in (start_y as i32..=end_y as i32).step_by(spacing as usize) {
-                let y = y as f32;
-                painter.line_segment(
-                    [
-                        egui::pos2(top_left.x, y) + self.offset,
-                        egui::pos2(bottom_right.x, y) + self.offset,
-                    ],
-                    stroke,
-                );
+    // Push an undo entry, dropping the oldest ones beyond `max_undo_depth`.
+    fn push_undo(&mut self, snapshot: ProjectSnapshot) {
+        self.dirty = true;
+        self.undo_stack.push(snapshot);
+        let excess = self
+            .undo_stack
+            .len()
+            .saturating_sub(self.preferences.max_undo_depth.max(1));
+        self.undo_stack.drain(..excess);
+    }
+
+    // Record state before a continuous edit such as dragging in a color picker. Calls within
+    // the debounce window of the previous one belong to the same gesture and share its entry.
+    fn record_state_coalesced(&mut self) {
+        let now = Instant::now();
+        if self
+            .last_coalesced_edit
+            .is_none_or(|last| now - last > EDIT_DEBOUNCE)
+        {
+            self.record_state();
+        }
+        self.last_coalesced_edit = Some(now);
+    }
+
+    // Commit the marker stroke once the pointer is released, as a single undo entry.
+    fn finish_stroke(&mut self, pointer_down: bool) {
+        if pointer_down {
+            // Reset the flag while drawing.
+            self.marker_stroke_recorded = false;
+            return;
+        }
+        if let Some(mut stroke) = self.current_stroke.take() {
+            // Smoothed in canvas units, so the result doesn't depend on the zoom.
+            stroke.points = smooth_stroke(&stroke.points, self.stroke_smoothing);
+            // Only record state once when the pointer is released.
+            if !self.marker_stroke_recorded {
+                self.record_state();
+                self.marker_stroke_recorded = true;
             }
+            self.strokes.push(stroke);
+        }
+    }
 
-            // Render Connections (same as before).
-            for connection in &self.connections {
-                let fallback_note = NoteNode {
-                    id: 0,
-                    position: egui::pos2(0.0, 0.0),
-                    size: egui::vec2(1.0, 1.0),
-                    text: String::new(),
-                    is_dragging: false,
-                    locked: false,
-                };
-                let fallback_code = CodeNode {
-                    id: 0,
-                    position: egui::pos2(0.0, 0.0),
-                    size: egui::vec2(1.0, 1.0),
-                    file_path: String::new(),
-                    code: String::new(),
-                    is_dragging: false,
-                    locked: false,
-                    line_offset: None,
-                };
+    // One undo entry per erase gesture, taken before its first change.
+    fn record_erase(&mut self, pointer_down: bool, hit: bool) {
+        if !pointer_down {
+            self.eraser_state_recorded = false;
+        } else if hit && !self.eraser_state_recorded {
+            self.record_state();
+            self.eraser_state_recorded = true;
+        }
+    }
 
-                let (start_pos, start_size) = if connection.start_node_type == NodeType::Note {
-                    let node = self
-                        .note_nodes
-                        .iter()
-                        .find(|n| n.id == connection.start_node_id)
-                        .unwrap_or(&fallback_note);
-                    (
-                        ((node.position * self.zoom) + self.offset),
-                        node.size * self.zoom,
-                    )
-                } else {
-                    let node = self
-                        .code_nodes
-                        .iter()
-                        .find(|n| n.id == connection.start_node_id)
-                        .unwrap_or(&fallback_code);
-                    (
-                        ((node.position * self.zoom) + self.offset),
-                        node.size * self.zoom,
-                    )
-                };
+    // Push the state from before the current typing burst as a single undo entry.
+    // The field may still have focus, so the next burst starts from the current state.
+    fn commit_text_edit(&mut self) {
+        if self.last_text_change.take().is_some() && self.text_edit_start.is_some() {
+            let snapshot = self.take_snapshot();
+            if let Some(before) = self.text_edit_start.replace(snapshot) {
+                self.push_undo(before);
+                self.redo_stack.clear();
+            }
+        }
+    }
 
-                let (end_pos, end_size) = if connection.end_node_type == NodeType::Note {
-                    let node = self
-                        .note_nodes
-                        .iter()
-                        .find(|n| n.id == connection.end_node_id)
-                        .unwrap_or(&fallback_note);
-                    (
-                        ((node.position * self.zoom) + self.offset),
-                        node.size * self.zoom,
-                    )
+    // Called once per frame with the focus/change events of all node text fields.
+    fn track_text_edits(&mut self, ctx: &egui::Context, gained: bool, changed: bool, lost: bool) {
+        if changed {
+            self.last_text_change = Some(Instant::now());
+        }
+        if lost {
+            self.commit_text_edit();
+            self.text_edit_start = None;
+        }
+        if gained && self.text_edit_start.is_none() {
+            self.text_edit_start = Some(self.take_snapshot());
+        }
+        if let Some(last_change) = self.last_text_change {
+            let idle = last_change.elapsed();
+            if idle >= EDIT_DEBOUNCE {
+                self.commit_text_edit();
+            } else {
+                ctx.request_repaint_after(EDIT_DEBOUNCE - idle);
+            }
+        }
+    }
+
+    // Position for a node created from the Tools overlay: new nodes are placed on a circle
+    // around the visible center, `step` turning by 45° each time so they don't stack.
+    fn spiral_position(&self, screen_center: egui::Pos2, step: usize) -> egui::Pos2 {
+        // Convert the visible center to canvas (logical) coordinates.
+        let canvas_center = self.to_canvas(screen_center);
+        let angle = (step as f32) * 45.0_f32.to_radians();
+        // Radius of 100 screen pixels, converted to canvas coordinates.
+        canvas_center + (egui::vec2(angle.cos(), angle.sin()) * 100.0) / self.zoom
+    }
+
+    // A canvas position snapped to the grid if snapping is on.
+    fn snapped(&self, position: egui::Pos2) -> egui::Pos2 {
+        if self.snap_to_grid {
+            snap_to_grid(position, self.grid_spacing)
+        } else {
+            position
+        }
+    }
+
+    // Create a note node at `position` (canvas coordinates), record state and return its id.
+    fn add_note_at(&mut self, position: egui::Pos2) -> usize {
+        self.record_state();
+        self.insert_note(position)
+    }
+
+    // Create a note node at `position` without recording state and return its id.
+    fn insert_note(&mut self, position: egui::Pos2) -> usize {
+        let position = self.snapped(position);
+        let id = self.next_note_id;
+        self.next_note_id += 1;
+        self.note_nodes.push(NoteNode {
+            id,
+            position,
+            size: DEFAULT_NOTE_SIZE,
+            text: String::new(),
+            is_dragging: false,
+            locked: false,
+            bg_color: NOTE_BACKGROUND,
+            text_color: NOTE_TEXT_COLOR,
+            render_markdown: false,
+            tags: Vec::new(),
+        });
+        id
+    }
+
+    // Create a note to the right of an existing node, connected from it, as a single undo
+    // step. Returns the new note's id.
+    fn add_linked_note(&mut self, from_type: NodeType, from_id: usize) -> Option<usize> {
+        let from_rect = self.node_rect(from_type, from_id)?;
+        self.record_state();
+        let id = self.insert_note(egui::pos2(from_rect.max.x + 60.0, from_rect.min.y));
+        let to_rect = self.node_rect(NodeType::Note, id)?;
+        let (start_side, end_side) = facing_sides(from_rect, to_rect);
+        self.connections.push(NodeConnection {
+            id: self.next_connection_id,
+            start_node_id: from_id,
+            start_node_type: from_type,
+            start_side,
+            end_node_id: id,
+            end_node_type: NodeType::Note,
+            end_side,
+            control_points: None,
+            color: egui::Color32::from_rgb(187, 192, 206),
+            label: String::new(),
+            undirected: false,
+            auto_sides: false,
+            arrow_head_size: default_arrow_head_size(),
+            offset_distance: default_offset_distance(),
+        });
+        self.next_connection_id += 1;
+        Some(id)
+    }
+
+    // Finish the connection started with the Arrow tool at the given end, as one undo step.
+    fn connect_to(&mut self, end_node_type: NodeType, end_node_id: usize, end_side: Side) {
+        let Some((start_node_id, start_node_type, start_side)) = self.connection_start.take()
+        else {
+            return;
+        };
+        self.record_state();
+        self.connections.push(NodeConnection {
+            id: self.next_connection_id,
+            start_node_id,
+            start_node_type,
+            start_side,
+            end_node_id,
+            end_node_type,
+            end_side,
+            control_points: None,
+            color: egui::Color32::from_rgb(187, 192, 206),
+            label: String::new(),
+            undirected: false,
+            auto_sides: false,
+            arrow_head_size: default_arrow_head_size(),
+            offset_distance: default_offset_distance(),
+        });
+        self.next_connection_id += 1;
+    }
+
+    // Create a code node at `position` (canvas coordinates), record state and return its id.
+    fn add_code_at(&mut self, position: egui::Pos2) -> usize {
+        self.record_state();
+        self.insert_code(position)
+    }
+
+    // Create a code node at `position` without recording state and return its id.
+    fn insert_code(&mut self, position: egui::Pos2) -> usize {
+        let position = self.snapped(position);
+        let id = self.next_note_id;
+        self.next_note_id += 1;
+        self.code_nodes.push(CodeNode {
+            id,
+            position,
+            size: DEFAULT_CODE_SIZE,
+            file_path: String::new(),
+            code: String::new(),
+            is_dragging: false,
+            locked: false,
+            line_offset: None,
+            theme: default_code_theme(),
+            bg_color: None,
+            sync: false,
+            synced_modified: None,
+            sync_lost: false,
+            language: None,
+            tags: Vec::new(),
+        });
+        id
+    }
+
+    // Create a code node for each file dropped on the canvas, cascading down from `position`
+    // (canvas coordinates), and record state once. The first file sets the project root if
+    // none is set yet. Files that aren't text are skipped with a toast.
+    fn add_dropped_files(&mut self, files: &[egui::DroppedFile], position: egui::Pos2) {
+        let mut created = 0;
+        for path in files.iter().filter_map(|file| file.path.as_ref()) {
+            let name = path.display().to_string();
+            let code = match fs::read(path).map(String::from_utf8) {
+                Ok(Ok(code)) if !code.contains('\0') => code,
+                Ok(_) => {
+                    self.show_toast(format!("{} is not a text file", name));
+                    continue;
+                }
+                Err(err) => {
+                    self.show_toast(format!("Could not read {}: {}", name, err));
+                    continue;
+                }
+            };
+            if self.project_root.is_none() {
+                self.project_root = path.parent().map(|parent| parent.to_path_buf());
+            }
+            // Files outside the root keep their full path, which `Path::join` leaves as is.
+            let file_path = self
+                .project_root
+                .as_deref()
+                .and_then(|root| path.strip_prefix(root).ok())
+                .unwrap_or(path.as_path())
+                .display()
+                .to_string();
+            // One undo step for all the files, recorded before the first node is added.
+            if created == 0 {
+                self.record_state();
+            }
+            let offset = egui::Vec2::splat(30.0) * created as f32 / self.zoom;
+            let id = self.insert_code(position + offset);
+            if let Some(node) = self.code_nodes.iter_mut().find(|n| n.id == id) {
+                node.file_path = file_path;
+                node.code = code;
+            }
+            created += 1;
+        }
+    }
+
+    // Re-read the files of synced code nodes that changed on disk and follow their snippet to
+    // where it is now. A snippet that can't be found anymore is flagged, not cleared.
+    fn sync_code_nodes(&mut self) {
+        let Some(root) = self.project_root.clone() else {
+            return;
+        };
+        let mut lost = Vec::new();
+        for node in self.code_nodes.iter_mut().filter(|n| n.sync && n.locked) {
+            let path = root.join(&node.file_path);
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            if modified.is_some() && modified == node.synced_modified {
+                continue;
+            }
+            node.synced_modified = modified;
+            let was_lost = node.sync_lost;
+            match fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| Some((locate_snippet(&node.code, &contents)?, contents)))
+            {
+                Some((line, contents)) => {
+                    // Take the lines as they are in the file now (line endings, trailing spaces).
+                    let line_count = node.code.trim_end().lines().count();
+                    node.code = contents
+                        .lines()
+                        .skip(line - 1)
+                        .take(line_count)
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    node.line_offset = Some(line);
+                    node.sync_lost = false;
+                }
+                None => node.sync_lost = true,
+            }
+            if node.sync_lost && !was_lost {
+                lost.push(node.file_path.clone());
+            }
+        }
+        for file_path in lost {
+            self.log_error(format!(
+                "Synced code node snippet no longer found in {}",
+                file_path
+            ));
+        }
+    }
+
+    // Background color a node is drawn with.
+    fn node_background(&self, node_type: NodeType, id: usize) -> egui::Color32 {
+        match node_type {
+            NodeType::Note => self
+                .note_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map_or(NOTE_BACKGROUND, |n| n.bg_color),
+            NodeType::Code => self
+                .code_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| n.bg_color.unwrap_or(code_theme(&n.theme).background))
+                .unwrap_or(NOTE_BACKGROUND),
+            NodeType::Image => NOTE_BACKGROUND,
+        }
+    }
+
+    // Tags of a node. Image nodes have none.
+    fn node_tags(&self, node_type: NodeType, id: usize) -> &[String] {
+        let tags = match node_type {
+            NodeType::Note => self.note_nodes.iter().find(|n| n.id == id).map(|n| &n.tags),
+            NodeType::Code => self.code_nodes.iter().find(|n| n.id == id).map(|n| &n.tags),
+            NodeType::Image => None,
+        };
+        tags.map_or(&[], |tags| tags.as_slice())
+    }
+
+    fn node_tags_mut(&mut self, node_type: NodeType, id: usize) -> Option<&mut Vec<String>> {
+        match node_type {
+            NodeType::Note => self
+                .note_nodes
+                .iter_mut()
+                .find(|n| n.id == id)
+                .map(|n| &mut n.tags),
+            NodeType::Code => self
+                .code_nodes
+                .iter_mut()
+                .find(|n| n.id == id)
+                .map(|n| &mut n.tags),
+            NodeType::Image => None,
+        }
+    }
+
+    // Every tag used on the board, sorted.
+    fn all_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self
+            .note_nodes
+            .iter()
+            .flat_map(|n| &n.tags)
+            .chain(self.code_nodes.iter().flat_map(|n| &n.tags))
+            .cloned()
+            .collect();
+        tags.sort_unstable();
+        tags.dedup();
+        tags
+    }
+
+    // Contents of a node's Tags menu: its tags, each with a remove button, and a field to add
+    // one. Each change is one undo step.
+    fn tags_menu(&mut self, ui: &mut egui::Ui, node_type: NodeType, id: usize) {
+        let mut remove = None;
+        for (k, tag) in self.node_tags(node_type, id).iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.colored_label(tag_color(tag), tag);
+                if ui.small_button("x").on_hover_text("Remove tag").clicked() {
+                    remove = Some(k);
+                }
+            });
+        }
+        let response = ui
+            .add(egui::TextEdit::singleline(&mut self.new_tag).hint_text("New tag"))
+            .on_hover_text("Press Enter to add");
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            let tag = self.new_tag.trim().to_string();
+            self.new_tag.clear();
+            if !tag.is_empty() && !self.node_tags(node_type, id).contains(&tag) {
+                self.record_state();
+                if let Some(tags) = self.node_tags_mut(node_type, id) {
+                    tags.push(tag);
+                }
+            }
+        }
+        if let Some(k) = remove {
+            self.record_state();
+            if let Some(tags) = self.node_tags_mut(node_type, id) {
+                tags.remove(k);
+            }
+        }
+    }
+
+    // Change the zoom while keeping the canvas point under `anchor` (a screen position) in place.
+    fn set_zoom_about(&mut self, anchor: egui::Pos2, zoom: egui::Vec2) {
+        let canvas_point = self.to_canvas(anchor);
+        self.zoom = zoom;
+        self.offset = anchor - (canvas_point.to_vec2() * zoom).to_pos2();
+    }
+
+    // Draw a marker or highlighter stroke, unless it is outside the painter's clip rect. A
+    // highlighter stroke is painted as one path, so its translucent segments don't darken
+    // where they join.
+    fn paint_stroke(&self, painter: &egui::Painter, stroke: &Stroke) {
+        let bounds = stroke.bounds();
+        let screen_bounds =
+            egui::Rect::from_two_pos(self.to_screen(bounds.min), self.to_screen(bounds.max))
+                .expand(stroke.max_width() * self.zoom.max_elem());
+        if !painter.clip_rect().intersects(screen_bounds) {
+            return;
+        }
+        match stroke.kind {
+            StrokeKind::Highlighter => {
+                let points: Vec<egui::Pos2> =
+                    stroke.points.iter().map(|p| self.to_screen(*p)).collect();
+                let line = egui::Stroke::new(stroke.thickness * self.zoom.min_elem(), stroke.color);
+                painter.add(egui::Shape::line(points, line));
+            }
+            StrokeKind::Marker => {
+                for ([a, b], width) in stroke.segments() {
+                    painter.line_segment(
+                        [self.to_screen(a), self.to_screen(b)],
+                        egui::Stroke::new(width * self.zoom.min_elem(), stroke.color),
+                    );
+                }
+            }
+        }
+    }
+
+    // Choose zoom and offset so the whole board fits in `screen`, within the usual zoom range.
+    // An empty board gets the default zoom with the origin in the middle.
+    fn fit_to_content(&mut self, screen: egui::Rect) {
+        self.pan_velocity = egui::Vec2::ZERO;
+        let Some(bounds) = self.content_bounds() else {
+            self.zoom = egui::Vec2::splat(2.0);
+            self.offset = screen.center().to_vec2();
+            return;
+        };
+        let available =
+            (screen.size() - egui::Vec2::splat(2.0 * FIT_MARGIN)).max(egui::Vec2::splat(1.0));
+        let zoom = (available / bounds.size().max(egui::Vec2::splat(1.0)))
+            .min_elem()
+            .clamp(0.4, 4.0);
+        self.zoom = egui::Vec2::splat(zoom);
+        self.offset = screen.center().to_vec2() - bounds.center().to_vec2() * self.zoom;
+    }
+
+    // Pan so the canvas origin is at `screen_center`, keeping the zoom.
+    fn center_view(&mut self, screen_center: egui::Pos2) {
+        self.pan_velocity = egui::Vec2::ZERO;
+        self.offset = screen_center.to_vec2();
+    }
+
+    // Nodes that are neither the start nor the end of any connection.
+    fn orphan_nodes(&self) -> Vec<(NodeType, usize)> {
+        let connected: HashSet<(NodeType, usize)> = self
+            .connections
+            .iter()
+            .flat_map(|c| {
+                [
+                    (c.start_node_type, c.start_node_id),
+                    (c.end_node_type, c.end_node_id),
+                ]
+            })
+            .collect();
+        self.note_nodes
+            .iter()
+            .map(|n| (NodeType::Note, n.id))
+            .chain(self.code_nodes.iter().map(|n| (NodeType::Code, n.id)))
+            .chain(self.image_nodes.iter().map(|n| (NodeType::Image, n.id)))
+            .filter(|key| !connected.contains(key))
+            .collect()
+    }
+
+    // Side panel listing a node's properties. Geometry and color edits share an undo entry
+    // per gesture; locking and tag edits record one of their own.
+    fn node_inspector(&mut self, ui: &mut egui::Ui, node_type: NodeType, id: usize) {
+        let Some(rect) = self.node_rect(node_type, id) else {
+            return;
+        };
+        let (mut position, mut size) = (rect.min, rect.size());
+        let mut color = self.node_background(node_type, id);
+        let (title, mut locked, details) = match node_type {
+            NodeType::Note => {
+                let note = self.note_nodes.iter().find(|n| n.id == id).unwrap();
+                ("Note", note.locked, None)
+            }
+            NodeType::Code => {
+                let node = self.code_nodes.iter().find(|n| n.id == id).unwrap();
+                let details = code_location_text(node, self.project_root.as_deref());
+                ("Code", node.locked, Some(details))
+            }
+            NodeType::Image => {
+                let node = self.image_nodes.iter().find(|n| n.id == id).unwrap();
+                (
+                    "Image",
+                    node.locked,
+                    Some(node.source.description().to_string()),
+                )
+            }
+        };
+
+        ui.heading(format!("{} #{}", title, id));
+        let mut geometry_changed = false;
+        let mut color_changed = false;
+        let mut lock_changed = false;
+        egui::Grid::new("inspector_grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Position");
+                ui.horizontal(|ui| {
+                    geometry_changed |= ui
+                        .add(egui::DragValue::new(&mut position.x).prefix("x "))
+                        .changed();
+                    geometry_changed |= ui
+                        .add(egui::DragValue::new(&mut position.y).prefix("y "))
+                        .changed();
+                });
+                ui.end_row();
+                ui.label("Size");
+                ui.horizontal(|ui| {
+                    geometry_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut size.x)
+                                .range(1.0..=400.0)
+                                .prefix("w "),
+                        )
+                        .changed();
+                    geometry_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut size.y)
+                                .range(1.0..=400.0)
+                                .prefix("h "),
+                        )
+                        .changed();
+                });
+                ui.end_row();
+                // Images cover their whole node, so they have no background.
+                if node_type != NodeType::Image {
+                    ui.label("Background");
+                    color_changed = egui::color_picker::color_edit_button_srgba(
+                        ui,
+                        &mut color,
+                        egui::color_picker::Alpha::Opaque,
+                    )
+                    .changed();
+                    ui.end_row();
+                }
+                ui.label("Locked");
+                lock_changed = ui.checkbox(&mut locked, "").changed();
+                ui.end_row();
+            });
+        if let Some(details) = details {
+            ui.separator();
+            ui.label(details);
+        }
+
+        if geometry_changed || color_changed {
+            self.record_state_coalesced();
+        }
+        if lock_changed {
+            self.record_state();
+        }
+        let project_root = self.project_root.clone();
+        match node_type {
+            NodeType::Note => {
+                let note = self.note_nodes.iter_mut().find(|n| n.id == id).unwrap();
+                note.position = position;
+                note.size = size;
+                if color_changed {
+                    note.bg_color = color;
+                }
+                note.locked = locked;
+            }
+            NodeType::Code => {
+                let node = self.code_nodes.iter_mut().find(|n| n.id == id).unwrap();
+                node.position = position;
+                node.size = size;
+                if color_changed {
+                    node.bg_color = Some(color);
+                }
+                if lock_changed && locked {
+                    lock_code_node(node, project_root.as_deref());
+                } else {
+                    node.locked = locked;
+                }
+            }
+            NodeType::Image => {
+                let node = self.image_nodes.iter_mut().find(|n| n.id == id).unwrap();
+                node.position = position;
+                node.size = size;
+                node.locked = locked;
+            }
+        }
+        ui.separator();
+        ui.label("Tags");
+        self.tags_menu(ui, node_type, id);
+    }
+
+    // Duplicate the given nodes as one undo step and select the copies.
+    fn duplicate_and_select(&mut self, nodes: &[(NodeType, usize)]) {
+        if nodes.is_empty() {
+            return;
+        }
+        self.record_state();
+        self.selected_nodes = self.duplicate_nodes(nodes);
+        self.selected_node = match self.selected_nodes.as_slice() {
+            &[key] => Some(key),
+            _ => None,
+        };
+    }
+
+    // Copy the given nodes, plus the connections running between two of them, as a new
+    // subgraph next to the original. Returns the copies' keys.
+    fn duplicate_nodes(&mut self, nodes: &[(NodeType, usize)]) -> Vec<(NodeType, usize)> {
+        let shift = egui::vec2(20.0, 20.0);
+        let mut new_ids = HashMap::new();
+        for &(node_type, id) in nodes {
+            let new_id = self.next_note_id;
+            match node_type {
+                NodeType::Note => {
+                    let Some(node) = self.note_nodes.iter().find(|n| n.id == id) else {
+                        continue;
+                    };
+                    let copy = NoteNode {
+                        id: new_id,
+                        position: node.position + shift,
+                        is_dragging: false,
+                        ..node.clone()
+                    };
+                    self.note_nodes.push(copy);
+                }
+                NodeType::Code => {
+                    let Some(node) = self.code_nodes.iter().find(|n| n.id == id) else {
+                        continue;
+                    };
+                    let copy = CodeNode {
+                        id: new_id,
+                        position: node.position + shift,
+                        is_dragging: false,
+                        ..node.clone()
+                    };
+                    self.code_nodes.push(copy);
+                }
+                NodeType::Image => {
+                    let Some(node) = self.image_nodes.iter().find(|n| n.id == id) else {
+                        continue;
+                    };
+                    let copy = ImageNode {
+                        id: new_id,
+                        position: node.position + shift,
+                        is_dragging: false,
+                        ..node.clone()
+                    };
+                    self.image_nodes.push(copy);
+                }
+            }
+            self.next_note_id += 1;
+            new_ids.insert((node_type, id), new_id);
+        }
+        let internal: Vec<NodeConnection> = self
+            .connections
+            .iter()
+            .filter_map(|conn| {
+                let start = new_ids.get(&(conn.start_node_type, conn.start_node_id))?;
+                let end = new_ids.get(&(conn.end_node_type, conn.end_node_id))?;
+                Some(NodeConnection {
+                    start_node_id: *start,
+                    end_node_id: *end,
+                    ..conn.clone()
+                })
+            })
+            .collect();
+        for mut conn in internal {
+            conn.id = self.next_connection_id;
+            self.next_connection_id += 1;
+            self.connections.push(conn);
+        }
+        nodes
+            .iter()
+            .filter_map(|key| new_ids.get(key).map(|id| (key.0, *id)))
+            .collect()
+    }
+
+    // Bezier geometry of a connection under the view transform `canvas * zoom + offset`.
+    // `connection` must be an element of `self.connections` so arrows sharing a node side
+    // can be spread out. Returns `None` if either endpoint node no longer exists.
+    fn connection_curve(
+        &self,
+        connection: &NodeConnection,
+        zoom: egui::Vec2,
+        offset: egui::Vec2,
+    ) -> Option<ConnectionCurve> {
+        let start_rect = self.node_rect(connection.start_node_type, connection.start_node_id)?;
+        let end_rect = self.node_rect(connection.end_node_type, connection.end_node_id)?;
+        let start_pos = (start_rect.min.to_vec2() * zoom).to_pos2() + offset;
+        let start_size = start_rect.size() * zoom;
+        let end_pos = (end_rect.min.to_vec2() * zoom).to_pos2() + offset;
+        let end_size = end_rect.size() * zoom;
+
+        let (start_index, total_start) = get_arrow_index(
+            &self.connections,
+            connection.start_node_type,
+            connection.start_node_id,
+            connection.start_side,
+            connection,
+        );
+        let mut start = connection_point(
+            start_pos,
+            start_size,
+            connection.start_side,
+            start_index,
+            total_start,
+        );
+        let (end_index, total_end) = get_arrow_index(
+            &self.connections,
+            connection.end_node_type,
+            connection.end_node_id,
+            connection.end_side,
+            connection,
+        );
+        let mut end =
+            connection_point(end_pos, end_size, connection.end_side, end_index, total_end);
+
+        // Manual control points are stored in canvas coordinates; without them the curve bows
+        // out from the node sides by `offset_distance`.
+        let is_loop = connection.start_node_type == connection.end_node_type
+            && connection.start_node_id == connection.end_node_id;
+        let (control1, control2) = match connection.control_points {
+            Some((c1, c2)) => (
+                (c1.to_vec2() * zoom).to_pos2() + offset,
+                (c2.to_vec2() * zoom).to_pos2() + offset,
+            ),
+            None if is_loop => {
+                let [loop_start, control1, control2, loop_end] = self_loop_points(
+                    egui::Rect::from_min_size(start_pos, start_size),
+                    (start, connection.start_side),
+                    (end, connection.end_side),
+                    connection.offset_distance,
+                );
+                start = loop_start;
+                end = loop_end;
+                (control1, control2)
+            }
+            None => {
+                let d = end - start;
+                let normal_start = side_normal(connection.start_side);
+                let normal_end = side_normal(connection.end_side);
+                (
+                    start + d * 0.3 + normal_start * connection.offset_distance,
+                    start + d * 0.7 + normal_end * connection.offset_distance,
+                )
+            }
+        };
+        Some(ConnectionCurve {
+            control1,
+            control2,
+            end,
+            points: compute_cubic_bezier_points(start, control1, control2, end, 30),
+        })
+    }
+
+    // Point auto-sided connections out of the sides facing each other, for the nodes' current
+    // positions.
+    fn update_auto_sides(&mut self) {
+        for index in 0..self.connections.len() {
+            let conn = &self.connections[index];
+            // A loop keeps the sides it was drawn with; there is no other node to face.
+            let is_loop = conn.start_node_type == conn.end_node_type
+                && conn.start_node_id == conn.end_node_id;
+            if !conn.auto_sides || is_loop {
+                continue;
+            }
+            let Some(start_rect) = self.node_rect(conn.start_node_type, conn.start_node_id) else {
+                continue;
+            };
+            let Some(end_rect) = self.node_rect(conn.end_node_type, conn.end_node_id) else {
+                continue;
+            };
+            let (start_side, end_side) = facing_sides(start_rect, end_rect);
+            let conn = &mut self.connections[index];
+            conn.start_side = start_side;
+            conn.end_side = end_side;
+        }
+    }
+
+    // Bounding box of all nodes and strokes in canvas coordinates, or `None` for an empty board.
+    fn content_bounds(&self) -> Option<egui::Rect> {
+        let node_rects = self
+            .note_nodes
+            .iter()
+            .map(|n| egui::Rect::from_min_size(n.position, n.size))
+            .chain(
+                self.code_nodes
+                    .iter()
+                    .map(|n| egui::Rect::from_min_size(n.position, n.size)),
+            )
+            .chain(
+                self.image_nodes
+                    .iter()
+                    .map(|n| egui::Rect::from_min_size(n.position, n.size)),
+            );
+        let stroke_points = self
+            .strokes
+            .iter()
+            .flat_map(|s| s.points.iter())
+            .map(|p| egui::Rect::from_min_max(*p, *p));
+        let measurement_rects = self
+            .measurements
+            .iter()
+            .map(|m| egui::Rect::from_two_pos(m.start, m.end));
+        let shape_rects = self
+            .shapes
+            .iter()
+            .map(|s| egui::Rect::from_two_pos(s.start, s.end));
+        node_rects
+            .chain(stroke_points)
+            .chain(measurement_rects)
+            .chain(shape_rects)
+            .reduce(|a, b| a.union(b))
+    }
+
+    // Render the whole board into an image at `scale` pixels per canvas unit, independent
+    // of the current view. An empty board yields a small blank image.
+    fn render_image(&self, scale: f32) -> image::RgbaImage {
+        let margin = 20.0;
+        let bounds = self
+            .content_bounds()
+            .unwrap_or(egui::Rect::ZERO)
+            .expand(margin);
+        let scale = scale.min(MAX_EXPORT_SIZE / bounds.width().max(bounds.height()));
+        let offset = -bounds.min.to_vec2() * scale;
+        let to_image = |p: egui::Pos2| (p * scale) + offset;
+        let background = egui::Color32::from_rgb(40, 44, 52);
+        let mut canvas = raster::new_canvas(
+            (bounds.width() * scale).ceil() as u32,
+            (bounds.height() * scale).ceil() as u32,
+            background,
+        );
+        let font = raster::monospace_font();
+
+        for stroke in self
+            .strokes
+            .iter()
+            .filter(|s| s.kind == StrokeKind::Highlighter)
+        {
+            let points: Vec<egui::Pos2> = stroke.points.iter().map(|p| to_image(*p)).collect();
+            raster::polyline(&mut canvas, &points, stroke.thickness * scale, stroke.color);
+        }
+        for connection in &self.connections {
+            let Some(curve) = self.connection_curve(connection, egui::Vec2::splat(scale), offset)
+            else {
+                continue;
+            };
+            raster::polyline(&mut canvas, &curve.points, 2.0, connection.color);
+            if !connection.undirected {
+                let (arrow_left, arrow_right) = curve.arrow_head(connection.arrow_head_size);
+                raster::polyline(
+                    &mut canvas,
+                    &[arrow_left, curve.end, arrow_right],
+                    2.0,
+                    connection.color,
+                );
+            }
+            if let (Some(font), false) = (&font, connection.label.is_empty()) {
+                raster::label(
+                    &mut canvas,
+                    font,
+                    curve.midpoint(),
+                    6.0 * scale,
+                    egui::Color32::from_rgb(187, 192, 206),
+                    background,
+                    &connection.label,
+                );
+            }
+        }
+        for stroke in self.strokes.iter().filter(|s| s.kind == StrokeKind::Marker) {
+            for ([a, b], width) in stroke.segments() {
+                raster::polyline(
+                    &mut canvas,
+                    &[to_image(a), to_image(b)],
+                    width * scale,
+                    stroke.color,
+                );
+            }
+        }
+        for shape in &self.shapes {
+            let points: Vec<egui::Pos2> = shape.outline().into_iter().map(to_image).collect();
+            raster::polyline(&mut canvas, &points, shape.thickness * scale, shape.color);
+        }
+        for measurement in &self.measurements {
+            let (start, end) = (to_image(measurement.start), to_image(measurement.end));
+            raster::polyline(&mut canvas, &[start, end], 1.5, MEASURE_COLOR);
+            for head in measurement_heads(start, end, 8.0) {
+                raster::polyline(&mut canvas, &head, 1.5, MEASURE_COLOR);
+            }
+            if let Some(font) = &font {
+                raster::label(
+                    &mut canvas,
+                    font,
+                    egui::Rect::from_two_pos(start, end).center(),
+                    6.0 * scale,
+                    MEASURE_COLOR,
+                    background,
+                    &format!("{:.0}", measurement.length()),
+                );
+            }
+        }
+
+        // Images that can't be loaded are drawn as empty frames.
+        for node in &self.image_nodes {
+            let rect = egui::Rect::from_min_max(
+                to_image(node.position),
+                to_image(node.position + node.size),
+            );
+            match node
+                .source
+                .bytes(self.project_root.as_deref())
+                .and_then(|bytes| decode_image(&bytes))
+            {
+                Ok(picture) => raster::image(&mut canvas, rect, &picture),
+                Err(_) => raster::rect(
+                    &mut canvas,
+                    rect,
+                    NOTE_BACKGROUND,
+                    egui::Color32::from_rgb(80, 80, 80),
+                ),
+            }
+        }
+        let text_color = egui::Color32::from_rgb(187, 192, 206);
+        for note in &self.note_nodes {
+            let rect = egui::Rect::from_min_max(
+                to_image(note.position),
+                to_image(note.position + note.size),
+            );
+            raster::rect(
+                &mut canvas,
+                rect,
+                note.bg_color,
+                egui::Color32::from_rgb(80, 80, 80),
+            );
+            if let Some(font) = &font {
+                let clip = rect.shrink(2.0 * scale);
+                raster::text_block(
+                    &mut canvas,
+                    font,
+                    clip,
+                    6.0 * scale,
+                    note.text_color,
+                    &note.text,
+                );
+            }
+        }
+        for node in &self.code_nodes {
+            let theme = code_theme(&node.theme);
+            let rect = egui::Rect::from_min_max(
+                to_image(node.position),
+                to_image(node.position + node.size),
+            );
+            raster::rect(
+                &mut canvas,
+                rect,
+                node.bg_color.unwrap_or(theme.background),
+                egui::Color32::from_rgb(100, 100, 100),
+            );
+            let Some(font) = &font else {
+                continue;
+            };
+            let font_size = 5.0 * scale;
+            let header = egui::Rect::from_min_size(
+                rect.min,
+                egui::vec2(rect.width(), (font_size * 1.4).min(rect.height())),
+            );
+            raster::rect(&mut canvas, header, text_color, text_color);
+            raster::text_block(
+                &mut canvas,
+                font,
+                header.shrink(1.0),
+                font_size,
+                egui::Color32::BLACK,
+                &node.file_path,
+            );
+            let code = if node.locked {
+                let offset_val = node.line_offset.unwrap_or(1);
+                node.code
+                    .lines()
+                    .enumerate()
+                    .map(|(i, line)| format!("{:>4}: {}", i + offset_val, line))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            } else {
+                node.code.clone()
+            };
+            let body = egui::Rect::from_min_max(egui::pos2(rect.min.x, header.max.y), rect.max);
+            raster::text_block(
+                &mut canvas,
+                font,
+                body.shrink(1.0),
+                font_size,
+                theme.text,
+                &code,
+            );
+        }
+        canvas.0
+    }
+
+    // Write the whole board to a PNG file at `export_scale` pixels per canvas unit.
+    fn export_png(&self, path: &str) -> io::Result<()> {
+        self.render_image(self.export_scale)
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(io::Error::other)
+    }
+
+    // Write the whole board to an SVG file, in canvas coordinates.
+    fn export_svg(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.render_svg())
+    }
+
+    // The whole board as an SVG document drawn like `render_image`, with canvas units as user
+    // units. The view box covers the content plus a margin; an empty board gives a small
+    // blank document.
+    fn render_svg(&self) -> String {
+        let bounds = self
+            .content_bounds()
+            .unwrap_or(egui::Rect::ZERO)
+            .expand(20.0);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{:.2} {:.2} {:.2} {:.2}\" \
+             width=\"{:.2}\" height=\"{:.2}\">\n",
+            bounds.min.x,
+            bounds.min.y,
+            bounds.width(),
+            bounds.height(),
+            bounds.width(),
+            bounds.height()
+        );
+        svg += &svg_rect(bounds, egui::Color32::from_rgb(40, 44, 52), None);
+
+        for stroke in self
+            .strokes
+            .iter()
+            .filter(|s| s.kind == StrokeKind::Highlighter)
+        {
+            svg += &svg_polyline(&stroke.points, stroke.thickness, stroke.color);
+        }
+        for connection in &self.connections {
+            let Some(curve) =
+                self.connection_curve(connection, egui::Vec2::splat(1.0), egui::Vec2::ZERO)
+            else {
+                continue;
+            };
+            svg += &svg_polyline(&curve.points, 2.0, connection.color);
+            if !connection.undirected {
+                let (arrow_left, arrow_right) = curve.arrow_head(connection.arrow_head_size);
+                svg += &svg_polyline(&[arrow_left, curve.end, arrow_right], 2.0, connection.color);
+            }
+            if !connection.label.is_empty() {
+                svg += &svg_text(
+                    curve.midpoint(),
+                    6.0,
+                    egui::Color32::from_rgb(187, 192, 206),
+                    &connection.label,
+                    true,
+                );
+            }
+        }
+        for stroke in self.strokes.iter().filter(|s| s.kind == StrokeKind::Marker) {
+            if stroke.widths.is_empty() {
+                svg += &svg_polyline(&stroke.points, stroke.thickness, stroke.color);
+            } else {
+                for (segment, width) in stroke.segments() {
+                    svg += &svg_polyline(&segment, width, stroke.color);
+                }
+            }
+        }
+        for shape in &self.shapes {
+            svg += &svg_polyline(&shape.outline(), shape.thickness, shape.color);
+        }
+        for measurement in &self.measurements {
+            let (start, end) = (measurement.start, measurement.end);
+            svg += &svg_polyline(&[start, end], 1.5, MEASURE_COLOR);
+            for head in measurement_heads(start, end, 4.0) {
+                svg += &svg_polyline(&head, 1.5, MEASURE_COLOR);
+            }
+            svg += &svg_text(
+                egui::Rect::from_two_pos(start, end).center(),
+                6.0,
+                MEASURE_COLOR,
+                &format!("{:.0}", measurement.length()),
+                true,
+            );
+        }
+
+        // Images are embedded as data URLs; those that can't be read are drawn as empty frames.
+        for node in &self.image_nodes {
+            let rect = egui::Rect::from_min_size(node.position, node.size);
+            svg += &match node.source.bytes(self.project_root.as_deref()) {
+                Ok(bytes) => svg_image(rect, &bytes),
+                Err(_) => svg_rect(
+                    rect,
+                    NOTE_BACKGROUND,
+                    Some(egui::Color32::from_rgb(80, 80, 80)),
+                ),
+            };
+        }
+
+        // Nodes are nested documents, which clip the text to the node.
+        let text_color = egui::Color32::from_rgb(187, 192, 206);
+        for note in &self.note_nodes {
+            let rect = egui::Rect::from_min_size(egui::Pos2::ZERO, note.size);
+            svg += &svg_viewport(egui::Rect::from_min_size(note.position, note.size));
+            svg += &svg_rect(
+                rect,
+                note.bg_color,
+                Some(egui::Color32::from_rgb(80, 80, 80)),
+            );
+            svg += &svg_text(
+                rect.min + egui::vec2(2.0, 2.0),
+                6.0,
+                note.text_color,
+                &note.text,
+                false,
+            );
+            svg += "</svg>\n";
+        }
+        for node in &self.code_nodes {
+            let theme = code_theme(&node.theme);
+            let rect = egui::Rect::from_min_size(egui::Pos2::ZERO, node.size);
+            let font_size: f32 = 5.0;
+            let header = egui::Rect::from_min_size(
+                rect.min,
+                egui::vec2(rect.width(), (font_size * 1.4).min(rect.height())),
+            );
+            svg += &svg_viewport(egui::Rect::from_min_size(node.position, node.size));
+            svg += &svg_rect(
+                rect,
+                node.bg_color.unwrap_or(theme.background),
+                Some(egui::Color32::from_rgb(100, 100, 100)),
+            );
+            svg += &svg_rect(header, text_color, None);
+            svg += &svg_text(
+                header.min + egui::vec2(1.0, 0.0),
+                font_size,
+                egui::Color32::BLACK,
+                &node.file_path,
+                false,
+            );
+            let code = if node.locked {
+                let offset_val = node.line_offset.unwrap_or(1);
+                node.code
+                    .lines()
+                    .enumerate()
+                    .map(|(i, line)| format!("{:>4}: {}", i + offset_val, line))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            } else {
+                node.code.clone()
+            };
+            svg += &svg_text(
+                egui::pos2(1.0, header.max.y + 1.0),
+                font_size,
+                theme.text,
+                &code,
+                false,
+            );
+            svg += "</svg>\n";
+        }
+        svg += "</svg>\n";
+        svg
+    }
+
+    // Write a small preview image of the board next to the project file.
+    fn save_thumbnail(&self, project_path: &str) -> io::Result<()> {
+        let bounds = self
+            .content_bounds()
+            .unwrap_or(egui::Rect::ZERO)
+            .expand(20.0);
+        let scale = THUMBNAIL_SIZE / bounds.width().max(bounds.height());
+        self.render_image(scale)
+            .save(format!("{}.thumb.png", project_path))
+            .map_err(io::Error::other)
+    }
+
+    // Render the board at the default zoom and put it on the system clipboard.
+    fn copy_image_to_clipboard(&mut self) -> io::Result<()> {
+        let image = self.render_image(2.0);
+        let (width, height) = image.dimensions();
+        // arboard expects tightly packed, non-premultiplied RGBA rows, as `RgbaImage` stores them.
+        let data = arboard::ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: std::borrow::Cow::Owned(image.into_raw()),
+        };
+        self.clipboard()?.set_image(data).map_err(io::Error::other)
+    }
+
+    // The system clipboard, opened on first use. It is kept alive: on X11 its owner must
+    // outlive a copy for pasting to work.
+    fn clipboard(&mut self) -> io::Result<&mut arboard::Clipboard> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new().map_err(io::Error::other)?);
+        }
+        Ok(self.clipboard.as_mut().unwrap())
+    }
+
+    // Create a note holding the clipboard text at `position` (canvas coordinates) as one undo
+    // step and return its id.
+    fn paste_note_at(&mut self, position: egui::Pos2) -> io::Result<usize> {
+        let text = self.clipboard()?.get_text().map_err(io::Error::other)?;
+        self.record_state();
+        let id = self.insert_note(position);
+        if let Some(note) = self.note_nodes.iter_mut().find(|n| n.id == id) {
+            note.text = text;
+        }
+        Ok(id)
+    }
+
+    // Put a node on the system clipboard: a note as its text, a code or image node as JSON
+    // that `paste_text_at` turns back into a node.
+    fn copy_node(&mut self, ctx: &egui::Context, node_type: NodeType, id: usize) {
+        let copied = match node_type {
+            NodeType::Note => {
+                if let Some(note) = self.note_nodes.iter().find(|n| n.id == id) {
+                    ctx.copy_text(note.text.clone());
+                }
+                return;
+            }
+            NodeType::Code => self
+                .code_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| CopiedNode::Code(n.clone())),
+            NodeType::Image => self
+                .image_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| CopiedNode::Image(n.clone())),
+        };
+        let Some(copied) = copied else {
+            return;
+        };
+        match serde_json::to_string(&copied) {
+            Ok(json) => ctx.copy_text(format!("{}{}", COPIED_NODE_PREFIX, json)),
+            Err(e) => self.log_error(format!("Copy error: {}", e)),
+        }
+    }
+
+    // Create a node from pasted clipboard text at `position` (canvas coordinates) as one undo
+    // step: the copied code or image node if the text holds one, otherwise a note with the
+    // text. The node gets a fresh id.
+    fn paste_text_at(&mut self, position: egui::Pos2, text: &str) -> (NodeType, usize) {
+        let copied = text
+            .strip_prefix(COPIED_NODE_PREFIX)
+            .and_then(|json| serde_json::from_str::<CopiedNode>(json).ok());
+        self.record_state();
+        let position = self.snapped(position);
+        let id = self.next_note_id;
+        match copied {
+            Some(CopiedNode::Code(node)) => {
+                self.next_note_id += 1;
+                self.code_nodes.push(CodeNode {
+                    id,
+                    position,
+                    is_dragging: false,
+                    ..node
+                });
+                (NodeType::Code, id)
+            }
+            Some(CopiedNode::Image(node)) => {
+                self.next_note_id += 1;
+                self.image_nodes.push(ImageNode {
+                    id,
+                    position,
+                    is_dragging: false,
+                    ..node
+                });
+                (NodeType::Image, id)
+            }
+            None => {
+                let id = self.insert_note(position);
+                if let Some(note) = self.note_nodes.iter_mut().find(|n| n.id == id) {
+                    note.text = text.to_owned();
+                }
+                (NodeType::Note, id)
+            }
+        }
+    }
+
+    // Create an image node showing `source` at `position` (canvas coordinates) as one undo
+    // step and return its id. It gets the picture's aspect ratio at the default width.
+    fn add_image_at(&mut self, position: egui::Pos2, source: ImageSource) -> io::Result<usize> {
+        let picture = decode_image(&source.bytes(self.project_root.as_deref())?)?;
+        let (width, height) = picture.dimensions();
+        let size = egui::vec2(
+            DEFAULT_IMAGE_WIDTH,
+            DEFAULT_IMAGE_WIDTH * height as f32 / width.max(1) as f32,
+        )
+        .clamp(egui::Vec2::splat(1.0), egui::Vec2::splat(400.0));
+        self.record_state();
+        let id = self.next_note_id;
+        self.next_note_id += 1;
+        self.image_nodes.push(ImageNode {
+            id,
+            position: self.snapped(position),
+            size,
+            source,
+            is_dragging: false,
+            locked: false,
+        });
+        Ok(id)
+    }
+
+    // Ask for a PNG file and show it in a new image node at `position`. Files inside the
+    // project root are referenced relative to it, others by their full path.
+    fn add_image_dialog(&mut self, position: egui::Pos2) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("PNG image", &["png"])
+            .pick_file()
+        else {
+            return;
+        };
+        let file_path = self
+            .project_root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path.as_path())
+            .display()
+            .to_string();
+        if let Err(e) = self.add_image_at(position, ImageSource::Path(file_path)) {
+            self.log_error(format!("Could not add image {}: {}", path.display(), e));
+        }
+    }
+
+    // Create an image node holding the clipboard image at `position`, stored in the project
+    // itself as base64 PNG data.
+    fn paste_image_at(&mut self, position: egui::Pos2) -> io::Result<usize> {
+        let data = self.clipboard()?.get_image().map_err(io::Error::other)?;
+        let picture = image::RgbaImage::from_raw(
+            data.width as u32,
+            data.height as u32,
+            data.bytes.into_owned(),
+        )
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed clipboard image"))?;
+        let mut png = Vec::new();
+        picture
+            .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .map_err(io::Error::other)?;
+        self.add_image_at(position, ImageSource::Embedded(base64::encode(&png)))
+    }
+
+    // Texture of the image node at `index` in `image_nodes`, loaded on first use and again
+    // whenever its source changes. Failures are logged once and give `None`.
+    fn image_texture(&mut self, ctx: &egui::Context, index: usize) -> Option<egui::TextureHandle> {
+        let node = &self.image_nodes[index];
+        let id = node.id;
+        if let Some((source, texture)) = self.image_textures.get(&id) {
+            if *source == node.source {
+                return texture.clone();
+            }
+        }
+        let source = node.source.clone();
+        let texture = match source
+            .bytes(self.project_root.as_deref())
+            .and_then(|bytes| decode_image(&bytes))
+        {
+            Ok(picture) => {
+                let size = [picture.width() as usize, picture.height() as usize];
+                let pixels = egui::ColorImage::from_rgba_unmultiplied(size, picture.as_raw());
+                Some(ctx.load_texture(
+                    format!("image_node_{}", id),
+                    pixels,
+                    egui::TextureOptions::LINEAR,
+                ))
+            }
+            Err(e) => {
+                self.log_error(format!(
+                    "Could not load image {}: {}",
+                    source.description(),
+                    e
+                ));
+                None
+            }
+        };
+        self.image_textures.insert(id, (source, texture.clone()));
+        texture
+    }
+
+    // Code node paths are relative to the project root, so ask for one if none is set yet.
+    // Returns whether a root is set.
+    fn ensure_project_root(&mut self) -> bool {
+        if self.project_root.is_none() {
+            self.project_root = rfd::FileDialog::new().pick_folder();
+        }
+        self.project_root.is_some()
+    }
+
+    // Handle in the bottom right corner of a node's screen `rect` that resizes it when dragged,
+    // within the same range as the size fields. The whole drag is one undo step.
+    fn node_resize_handle(
+        &mut self,
+        ui: &mut egui::Ui,
+        node_type: NodeType,
+        id: usize,
+        rect: egui::Rect,
+    ) {
+        let handle_rect = egui::Rect::from_min_max(rect.max - egui::Vec2::splat(10.0), rect.max);
+        let response = ui
+            .interact(
+                handle_rect,
+                node_widget_id(node_type, id).with("resize"),
+                egui::Sense::drag(),
+            )
+            .on_hover_cursor(egui::CursorIcon::ResizeNwSe);
+        let color = if response.hovered() || response.dragged() {
+            SELECTION_COLOR
+        } else {
+            egui::Color32::from_gray(120)
+        };
+        for inset in [4.0, 8.0] {
+            ui.painter().line_segment(
+                [
+                    egui::pos2(rect.max.x - inset, rect.max.y - 2.0),
+                    egui::pos2(rect.max.x - 2.0, rect.max.y - inset),
+                ],
+                egui::Stroke::new(1.0, color),
+            );
+        }
+        if response.drag_started() {
+            self.record_state();
+        }
+        if response.dragged() {
+            let delta = response.drag_delta() / self.zoom;
+            let size = match node_type {
+                NodeType::Note => self
+                    .note_nodes
+                    .iter_mut()
+                    .find(|n| n.id == id)
+                    .map(|n| &mut n.size),
+                NodeType::Code => self
+                    .code_nodes
+                    .iter_mut()
+                    .find(|n| n.id == id)
+                    .map(|n| &mut n.size),
+                NodeType::Image => self
+                    .image_nodes
+                    .iter_mut()
+                    .find(|n| n.id == id)
+                    .map(|n| &mut n.size),
+            };
+            if let Some(size) = size {
+                *size = (*size + delta).clamp(egui::Vec2::splat(1.0), egui::Vec2::splat(400.0));
+            }
+        }
+    }
+
+    // Delete a node and its connections as one undo step.
+    fn delete_node(&mut self, node_type: NodeType, id: usize) {
+        self.record_state();
+        match node_type {
+            NodeType::Note => self.note_nodes.retain(|n| n.id != id),
+            NodeType::Code => self.code_nodes.retain(|n| n.id != id),
+            NodeType::Image => self.image_nodes.retain(|n| n.id != id),
+        }
+        self.prune_dangling_connections();
+        if self.selected_node == Some((node_type, id)) {
+            self.selected_node = None;
+        }
+        self.selected_nodes.retain(|key| *key != (node_type, id));
+    }
+
+    // Remove connections whose start or end node no longer exists. Call after deleting nodes.
+    fn prune_dangling_connections(&mut self) {
+        let live: HashSet<(NodeType, usize)> = self
+            .note_nodes
+            .iter()
+            .map(|n| (NodeType::Note, n.id))
+            .chain(self.code_nodes.iter().map(|n| (NodeType::Code, n.id)))
+            .chain(self.image_nodes.iter().map(|n| (NodeType::Image, n.id)))
+            .collect();
+        self.connections.retain(|c| {
+            live.contains(&(c.start_node_type, c.start_node_id))
+                && live.contains(&(c.end_node_type, c.end_node_id))
+        });
+        self.selected_connections
+            .retain(|id| self.connections.iter().any(|c| c.id == *id));
+    }
+
+    // Position of a node, for moving it.
+    fn node_position_mut(&mut self, node_type: NodeType, id: usize) -> Option<&mut egui::Pos2> {
+        match node_type {
+            NodeType::Note => self
+                .note_nodes
+                .iter_mut()
+                .find(|n| n.id == id)
+                .map(|n| &mut n.position),
+            NodeType::Code => self
+                .code_nodes
+                .iter_mut()
+                .find(|n| n.id == id)
+                .map(|n| &mut n.position),
+            NodeType::Image => self
+                .image_nodes
+                .iter_mut()
+                .find(|n| n.id == id)
+                .map(|n| &mut n.position),
+        }
+    }
+
+    // Nodes containing `query` (ignoring case) with a short description for the results
+    // list: the first line of a note's text, or a code node's file path. Code nodes match on
+    // their path or code, images on their file path.
+    fn search_nodes(&self, query: &str) -> Vec<(NodeType, usize, String)> {
+        let query = query.to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let notes = self
+            .note_nodes
+            .iter()
+            .filter(|n| n.text.to_lowercase().contains(&query))
+            .map(|n| {
+                let first_line = n.text.lines().next().unwrap_or_default();
+                (NodeType::Note, n.id, first_line.chars().take(40).collect())
+            });
+        let code = self
+            .code_nodes
+            .iter()
+            .filter(|n| {
+                n.file_path.to_lowercase().contains(&query)
+                    || n.code.to_lowercase().contains(&query)
+            })
+            .map(|n| (NodeType::Code, n.id, n.file_path.clone()));
+        let images = self
+            .image_nodes
+            .iter()
+            .filter(|n| match &n.source {
+                ImageSource::Path(path) => path.to_lowercase().contains(&query),
+                ImageSource::Embedded(_) => false,
+            })
+            .map(|n| (NodeType::Image, n.id, n.source.description().to_string()));
+        notes.chain(code).chain(images).collect()
+    }
+
+    // Pan so the node is centered in `screen`, keeping the zoom.
+    fn center_on_node(&mut self, node_type: NodeType, id: usize, screen: egui::Rect) {
+        if let Some(rect) = self.node_rect(node_type, id) {
+            self.pan_velocity = egui::Vec2::ZERO;
+            self.offset = screen.center().to_vec2() - rect.center().to_vec2() * self.zoom;
+        }
+    }
+
+    // Bounding rect of a node in canvas coordinates.
+    fn node_rect(&self, node_type: NodeType, id: usize) -> Option<egui::Rect> {
+        match node_type {
+            NodeType::Note => self
+                .note_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| egui::Rect::from_min_size(n.position, n.size)),
+            NodeType::Code => self
+                .code_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| egui::Rect::from_min_size(n.position, n.size)),
+            NodeType::Image => self
+                .image_nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| egui::Rect::from_min_size(n.position, n.size)),
+        }
+    }
+
+    // Export connections as CSV rows of `start_id,start_type,end_id,end_type,label`.
+    fn export_connections_csv(&self, file_path: &str) -> io::Result<()> {
+        let mut csv = String::from("start_id,start_type,end_id,end_type,label\n");
+        for conn in &self.connections {
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                conn.start_node_id,
+                conn.start_node_type.name(),
+                conn.end_node_id,
+                conn.end_node_type.name(),
+                csv_escape(&conn.label)
+            ));
+        }
+        let mut file = File::create(file_path)?;
+        file.write_all(csv.as_bytes())?;
+        Ok(())
+    }
+
+    // Add the connections of a CSV export to the board as one undo step. A row for a pair of
+    // nodes that is already connected only updates that connection's label.
+    fn import_connections_csv(&mut self, file_path: &str) -> io::Result<CsvImportSummary> {
+        let csv = std::fs::read_to_string(file_path)?;
+        let mut summary = CsvImportSummary::default();
+        let mut relabeled = Vec::new();
+        let mut added = Vec::new();
+        for record in parse_csv(&csv) {
+            if record.first().map(|f| f.trim()) == Some("start_id") {
+                continue; // Header row.
+            }
+            let parsed = match record.as_slice() {
+                [start_id, start_type, end_id, end_type, rest @ ..] => (|| {
+                    Some((
+                        start_id.trim().parse::<usize>().ok()?,
+                        NodeType::from_name(start_type)?,
+                        end_id.trim().parse::<usize>().ok()?,
+                        NodeType::from_name(end_type)?,
+                        rest.first().cloned().unwrap_or_default(),
+                    ))
+                })(),
+                _ => None,
+            };
+            let Some((start_id, start_type, end_id, end_type, label)) = parsed else {
+                summary.malformed += 1;
+                continue;
+            };
+            let (Some(start_rect), Some(end_rect)) = (
+                self.node_rect(start_type, start_id),
+                self.node_rect(end_type, end_id),
+            ) else {
+                summary.missing_nodes += 1;
+                continue;
+            };
+            let existing = self.connections.iter().position(|c| {
+                c.start_node_id == start_id
+                    && c.start_node_type == start_type
+                    && c.end_node_id == end_id
+                    && c.end_node_type == end_type
+            });
+            if let Some(index) = existing {
+                relabeled.push((index, label));
+                continue;
+            }
+            let (start_side, end_side) = facing_sides(start_rect, end_rect);
+            added.push(NodeConnection {
+                id: 0, // Assigned below.
+                start_node_id: start_id,
+                start_node_type: start_type,
+                start_side,
+                end_node_id: end_id,
+                end_node_type: end_type,
+                end_side,
+                control_points: None,
+                color: egui::Color32::from_rgb(187, 192, 206),
+                label,
+                undirected: false,
+                auto_sides: false,
+                arrow_head_size: default_arrow_head_size(),
+                offset_distance: default_offset_distance(),
+            });
+        }
+        summary.added = added.len();
+        if relabeled.is_empty() && added.is_empty() {
+            return Ok(summary);
+        }
+        self.record_state();
+        for (index, label) in relabeled {
+            self.connections[index].label = label;
+        }
+        for mut connection in added {
+            connection.id = self.next_connection_id;
+            self.next_connection_id += 1;
+            self.connections.push(connection);
+        }
+        Ok(summary)
+    }
+
+    fn undo(&mut self) {
+        self.commit_text_edit();
+        if let Some(snapshot) = self.undo_stack.pop() {
+            self.dirty = true;
+            self.redo_stack.push(self.take_snapshot());
+            self.restore_history_snapshot(snapshot);
+        }
+    }
+
+    fn redo(&mut self) {
+        self.commit_text_edit();
+        if let Some(snapshot) = self.redo_stack.pop() {
+            self.dirty = true;
+            self.undo_stack.push(self.take_snapshot());
+            self.restore_history_snapshot(snapshot);
+        }
+    }
+
+    // Keyboard shortcuts, unless a text field has focus (it handles its own undo, and Escape
+    // leaves the field).
+    fn handle_shortcuts(&mut self, ctx: &egui::Context, input: &egui::InputState) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        let command = input.modifiers.command;
+        let shift = input.modifiers.shift;
+        if input.key_pressed(egui::Key::Escape) {
+            self.deselect_all();
+        }
+        let delete =
+            input.key_pressed(egui::Key::Delete) || input.key_pressed(egui::Key::Backspace);
+        if let (true, Some((node_type, id))) = (delete, self.selected_node) {
+            self.delete_node(node_type, id);
+        }
+        if command && input.key_pressed(egui::Key::D) {
+            if let Some(key) = self.selected_node {
+                self.duplicate_and_select(&[key]);
+            }
+        }
+        // Ctrl+C and Ctrl+V arrive as copy and paste events rather than key presses. A pasted
+        // node lands at the pointer.
+        let copy = input.events.iter().any(|e| matches!(e, egui::Event::Copy));
+        let pasted = input.events.iter().find_map(|e| match e {
+            egui::Event::Paste(text) => Some(text.clone()),
+            _ => None,
+        });
+        if let (true, Some((node_type, id))) = (copy, self.selected_node) {
+            self.copy_node(ctx, node_type, id);
+        }
+        if let Some(text) = pasted {
+            let pointer = input
+                .pointer
+                .hover_pos()
+                .unwrap_or_else(|| input.screen_rect().center());
+            self.paste_text_at(self.to_canvas(pointer), &text);
+        }
+        // Arrow keys nudge the selected node by one canvas unit, ten with Shift. Holding a key
+        // repeats at the system's key repeat rate.
+        let step = if shift { 10.0 } else { 1.0 };
+        let nudge = [
+            (egui::Key::ArrowLeft, egui::vec2(-step, 0.0)),
+            (egui::Key::ArrowRight, egui::vec2(step, 0.0)),
+            (egui::Key::ArrowUp, egui::vec2(0.0, -step)),
+            (egui::Key::ArrowDown, egui::vec2(0.0, step)),
+        ]
+        .into_iter()
+        .fold(egui::Vec2::ZERO, |sum, (key, delta)| {
+            sum + delta * input.num_presses(key) as f32
+        });
+        if let (true, Some((node_type, id))) = (nudge != egui::Vec2::ZERO, self.selected_node) {
+            if self.nudge_start.is_none() {
+                self.commit_text_edit();
+                self.nudge_start = Some(self.take_snapshot());
+            }
+            if let Some(position) = self.node_position_mut(node_type, id) {
+                *position += nudge;
+            }
+        }
+        let (z, y) = (
+            input.key_pressed(egui::Key::Z),
+            input.key_pressed(egui::Key::Y),
+        );
+        if command && z && !shift {
+            self.undo();
+        } else if command && ((z && shift) || y) {
+            self.redo();
+        } else if command && shift && input.key_pressed(egui::Key::S) {
+            self.save_copy();
+        }
+    }
+}
+
+fn main() -> eframe::Result<()> {
+    // Optional project file to open, e.g. `cnf_infinity board.json`.
+    let project_path = std::env::args().nth(1);
+    // eframe restores the window size and position from its storage.
+    let options = eframe::NativeOptions {
+        persist_window: true,
+        ..Default::default()
+    };
+    eframe::run_native(
+        "CnF-Infinity",
+        options,
+        Box::new(|cc| Ok(Box::new(MyApp::new(cc, project_path)))),
+    )
+}
+
+fn compute_cubic_bezier_points(
+    p0: egui::Pos2,
+    p1: egui::Pos2,
+    p2: egui::Pos2,
+    p3: egui::Pos2,
+    segments: usize,
+) -> Vec<egui::Pos2> {
+    let mut points = Vec::with_capacity(segments + 1);
+    for i in 0..=segments {
+        let t = i as f32 / segments as f32;
+        let one_minus_t = 1.0 - t;
+        let a = one_minus_t.powi(3);
+        let b = 3.0 * t * one_minus_t.powi(2);
+        let c = 3.0 * t.powi(2) * one_minus_t;
+        let d = t.powi(3);
+        let x = a * p0.x + b * p1.x + c * p2.x + d * p3.x;
+        let y = a * p0.y + b * p1.y + c * p2.y + d * p3.y;
+        points.push(egui::pos2(x, y));
+    }
+    points
+}
+
+// Helper function: returns the outward normal for a given side.
+fn side_normal(side: Side) -> egui::Vec2 {
+    match side {
+        Side::Top => egui::vec2(0.0, -1.0),
+        Side::Bottom => egui::vec2(0.0, 1.0),
+        Side::Left => egui::vec2(-1.0, 0.0),
+        Side::Right => egui::vec2(1.0, 0.0),
+    }
+}
+
+// Helper function: ends and control points of a connection from a node to itself, looping
+// out of the node's screen `rect` by `offset`. Ends on the same side are spread apart so the
+// loop doesn't collapse, and ends on opposite sides go around the node.
+fn self_loop_points(
+    rect: egui::Rect,
+    (start, start_side): (egui::Pos2, Side),
+    (end, end_side): (egui::Pos2, Side),
+    offset: f32,
+) -> [egui::Pos2; 4] {
+    let offset = offset.max(20.0);
+    let normal_start = side_normal(start_side);
+    let normal_end = side_normal(end_side);
+    let tangent = normal_start.rot90();
+    // Length of the start side.
+    let extent = tangent.x.abs() * rect.width() + tangent.y.abs() * rect.height();
+    if start_side == end_side {
+        let start = start - tangent * extent * 0.2;
+        let end = end + tangent * extent * 0.2;
+        return [
+            start,
+            start + (normal_start - tangent * 0.5) * offset,
+            end + (normal_end + tangent * 0.5) * offset,
+            end,
+        ];
+    }
+    let around = if normal_start + normal_end == egui::Vec2::ZERO {
+        tangent * (extent + offset)
+    } else {
+        egui::Vec2::ZERO
+    };
+    [
+        start,
+        start + normal_start * offset + around,
+        end + normal_end * offset + around,
+        end,
+    ]
+}
+
+// Helper function: compute a connection point along a node's side.
+// If multiple arrows come from the same side, they are evenly distributed.
+fn connection_point(
+    node_pos: egui::Pos2,
+    node_size: egui::Vec2,
+    side: Side,
+    arrow_index: usize,
+    total: usize,
+) -> egui::Pos2 {
+    match side {
+        Side::Top => {
+            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
+            egui::pos2(node_pos.x + node_size.x * fraction, node_pos.y)
+        }
+        Side::Bottom => {
+            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
+            egui::pos2(
+                node_pos.x + node_size.x * fraction,
+                node_pos.y + node_size.y,
+            )
+        }
+        Side::Left => {
+            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
+            egui::pos2(node_pos.x, node_pos.y + node_size.y * fraction)
+        }
+        Side::Right => {
+            let fraction = (arrow_index + 1) as f32 / (total as f32 + 1.0);
+            egui::pos2(
+                node_pos.x + node_size.x,
+                node_pos.y + node_size.y * fraction,
+            )
+        }
+    }
+}
+
+// Vertical space reserved for a floating menu next to its anchor.
+const FLOATING_MENU_HEIGHT: f32 = 25.0;
+
+// Helper function: position a floating menu above `anchor`, flipping it below when there's
+// no room above, and clamp it to the screen so it always stays reachable.
+fn floating_menu_pos(anchor: egui::Rect, screen: egui::Rect) -> egui::Pos2 {
+    let y = if anchor.top() - FLOATING_MENU_HEIGHT >= screen.top() {
+        anchor.top() - FLOATING_MENU_HEIGHT
+    } else {
+        anchor.bottom()
+    };
+    let max_y = (screen.bottom() - FLOATING_MENU_HEIGHT).max(screen.top());
+    let x = anchor
+        .left()
+        .clamp(screen.left(), screen.right().max(screen.left()));
+    egui::pos2(x, y.clamp(screen.top(), max_y))
+}
+
+// Helper function: positions of the grid lines covering `min..=max`. Yields nothing when the
+// range isn't finite or would need more than `MAX_GRID_LINES` lines (e.g. a corrupted view).
+fn grid_lines(min: f32, max: f32, spacing: f32) -> impl Iterator<Item = f32> {
+    let spacing = if spacing.is_finite() {
+        spacing.max(1.0)
+    } else {
+        1.0
+    };
+    let first = (min / spacing).floor();
+    let last = (max / spacing).ceil();
+    let count = if first.is_finite() && last.is_finite() && last - first <= MAX_GRID_LINES {
+        (last - first).max(-1.0) as i64 + 1
+    } else {
+        0
+    };
+    (0..count).map(move |k| (first + k as f32) * spacing)
+}
+
+// Helper function: lock a code node, numbering its lines by locating the snippet in the
+// file it references (relative to the project root).
+fn lock_code_node(node: &mut CodeNode, project_root: Option<&std::path::Path>) {
+    node.locked = true;
+    if node.file_path.is_empty() {
+        return;
+    }
+    let Some(contents) =
+        project_root.and_then(|root| fs::read_to_string(root.join(&node.file_path)).ok())
+    else {
+        return;
+    };
+    node.line_offset = locate_snippet(&node.code, &contents);
+}
+
+// Helper function: first line (1-based) at which `snippet` appears in the file `contents`,
+// ignoring line ending style and trailing whitespace. Empty snippets are never found.
+fn locate_snippet(snippet: &str, contents: &str) -> Option<usize> {
+    let snippet_raw = snippet.replace("\r\n", "\n");
+    let snippet = snippet_raw.trim_end();
+    let file = contents.replace("\r\n", "\n");
+    let line_count = snippet.lines().count();
+    if line_count == 0 {
+        return None;
+    }
+    file.lines()
+        .collect::<Vec<_>>()
+        .windows(line_count)
+        .position(|window| window.join("\n").trim_end() == snippet)
+        .map(|i| i + 1)
+}
+
+// Helper function: tooltip for a code node's file path, with the resolved path and the
+// lines the snippet was found at (if it has been located).
+fn code_location_text(node: &CodeNode, project_root: Option<&std::path::Path>) -> String {
+    let mut text = node.file_path.clone();
+    if let Some(root) = project_root {
+        text.push_str(&format!("\n{}", root.join(&node.file_path).display()));
+    }
+    if let Some(first) = node.line_offset {
+        let last = first + node.code.trim_end().lines().count().max(1) - 1;
+        text.push_str(&format!("\nLines {}-{}", first, last));
+    }
+    text
+}
+
+// Syntax definitions and color themes for code highlighting, loaded once on first use.
+fn syntax_assets() -> &'static (syntect::parsing::SyntaxSet, syntect::highlighting::ThemeSet) {
+    static ASSETS: std::sync::OnceLock<(
+        syntect::parsing::SyntaxSet,
+        syntect::highlighting::ThemeSet,
+    )> = std::sync::OnceLock::new();
+    ASSETS.get_or_init(|| {
+        (
+            syntect::parsing::SyntaxSet::load_defaults_newlines(),
+            syntect::highlighting::ThemeSet::load_defaults(),
+        )
+    })
+}
+
+// Helper function: name of the syntax a code node is highlighted with: the one picked in its
+// editor, or else the one for its file extension. `None` if neither is known.
+fn code_syntax_name(node: &CodeNode) -> Option<&'static str> {
+    let (syntaxes, _) = syntax_assets();
+    let syntax = match &node.language {
+        Some(name) => syntaxes.find_syntax_by_name(name),
+        None => std::path::Path::new(&node.file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| syntaxes.find_syntax_by_extension(ext)),
+    };
+    syntax.map(|syntax| syntax.name.as_str())
+}
+
+// Helper function: the choices of a code node's language selector, `None` standing for the
+// file extension's language followed by the bundled syntaxes by name.
+fn language_options() -> impl Iterator<Item = Option<&'static str>> {
+    let mut names: Vec<&'static str> = syntax_assets()
+        .0
+        .syntaxes()
+        .iter()
+        .map(|syntax| syntax.name.as_str())
+        .collect();
+    names.sort_unstable();
+    std::iter::once(None).chain(names.into_iter().map(Some))
+}
+
+// Helper function: locked code with its line number gutter, colorized with the syntax named
+// `syntax_name`. Code in an unknown language is shown in the theme's text color.
+fn highlight_code(
+    code: &str,
+    syntax_name: &str,
+    first_line: usize,
+    theme: &CodeNodeTheme,
+    font_id: &egui::FontId,
+) -> egui::text::LayoutJob {
+    let (syntaxes, themes) = syntax_assets();
+    let mut highlighter = syntaxes
+        .find_syntax_by_name(syntax_name)
+        .zip(themes.themes.get(theme.syntax_theme))
+        .map(|(syntax, syntax_theme)| syntect::easy::HighlightLines::new(syntax, syntax_theme));
+    let plain = egui::TextFormat::simple(font_id.clone(), theme.text);
+    let mut job = egui::text::LayoutJob::default();
+    for (i, line) in code.lines().enumerate() {
+        if i > 0 {
+            job.append("\n", 0.0, plain.clone());
+        }
+        job.append(&format!("{:>4}: ", i + first_line), 0.0, plain.clone());
+        // The bundled syntaxes expect lines with their line ending.
+        let line_with_ending = format!("{}\n", line);
+        let regions = highlighter
+            .as_mut()
+            .and_then(|h| h.highlight_line(&line_with_ending, syntaxes).ok());
+        match regions {
+            Some(regions) => {
+                for (style, text) in regions {
+                    let fg = style.foreground;
+                    job.append(
+                        text.trim_end_matches('\n'),
+                        0.0,
+                        egui::TextFormat::simple(
+                            font_id.clone(),
+                            egui::Color32::from_rgb(fg.r, fg.g, fg.b),
+                        ),
+                    );
+                }
+            }
+            None => job.append(line, 0.0, plain.clone()),
+        }
+    }
+    job
+}
+
+// Memoizes `highlight_code` per frame, so unchanged code isn't re-highlighted every frame.
+#[derive(Default)]
+struct CodeHighlighter;
+
+impl egui::cache::ComputerMut<(&str, &str, usize, &str, &egui::FontId), egui::text::LayoutJob>
+    for CodeHighlighter
+{
+    fn compute(
+        &mut self,
+        (code, syntax_name, first_line, theme, font_id): (&str, &str, usize, &str, &egui::FontId),
+    ) -> egui::text::LayoutJob {
+        highlight_code(code, syntax_name, first_line, code_theme(theme), font_id)
+    }
+}
+
+type HighlightCache = egui::cache::FrameCache<egui::text::LayoutJob, CodeHighlighter>;
+
+// Helper function: a note's text as light markdown. Headings, bullet lists, bold, italic and
+// inline code are formatted; anything else is shown as written.
+fn markdown_job(text: &str, size: f32, color: egui::Color32) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            job.append(
+                "\n",
+                0.0,
+                egui::TextFormat::simple(egui::FontId::proportional(size), color),
+            );
+        }
+        let trimmed = line.trim_start();
+        let level = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            let scale = [1.6, 1.4, 1.2].get(level - 1).copied().unwrap_or(1.1);
+            append_markdown_inline(
+                &mut job,
+                trimmed[level..].trim_start(),
+                size * scale,
+                true,
+                color,
+            );
+            continue;
+        }
+        let content = match trimmed.get(..2) {
+            Some("- " | "* " | "+ ") => {
+                let indent = " ".repeat(line.len() - trimmed.len());
+                job.append(
+                    &format!("{}\u{2022} ", indent),
+                    0.0,
+                    egui::TextFormat::simple(egui::FontId::proportional(size), color),
+                );
+                &trimmed[2..]
+            }
+            _ => line,
+        };
+        append_markdown_inline(&mut job, content, size, false, color);
+    }
+    job
+}
+
+// Helper function: one line of markdown with `**bold**`, `*italic*` and `` `code` `` spans.
+// Markers without a closing partner are kept as text.
+fn append_markdown_inline(
+    job: &mut egui::text::LayoutJob,
+    text: &str,
+    size: f32,
+    strong: bool,
+    color: egui::Color32,
+) {
+    let plain = egui::TextFormat::simple(
+        egui::FontId::proportional(size),
+        if strong { strong_color(color) } else { color },
+    );
+    let mut pending = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let marker = if rest.starts_with("**") {
+            "**"
+        } else if c == '*' || c == '`' {
+            &rest[..1]
+        } else {
+            ""
+        };
+        let span = (!marker.is_empty())
+            .then(|| rest[marker.len()..].find(marker))
+            .flatten()
+            .filter(|&end| end > 0);
+        if let Some(end) = span {
+            job.append(&std::mem::take(&mut pending), 0.0, plain.clone());
+            let inner = &rest[marker.len()..marker.len() + end];
+            let format = match marker {
+                "**" => egui::TextFormat {
+                    color: strong_color(color),
+                    ..plain.clone()
+                },
+                "*" => egui::TextFormat {
+                    italics: true,
+                    ..plain.clone()
+                },
+                _ => egui::TextFormat {
+                    font_id: egui::FontId::monospace(size),
+                    background: egui::Color32::from_black_alpha(60),
+                    ..plain.clone()
+                },
+            };
+            job.append(inner, 0.0, format);
+            rest = &rest[2 * marker.len() + end..];
+        } else {
+            pending.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    job.append(&pending, 0.0, plain);
+}
+
+// Helper function: emphasized variant of a text color, for bold text and headings. Light text
+// gets lighter and dark text darker, so it stands out on the note's background either way.
+fn strong_color(color: egui::Color32) -> egui::Color32 {
+    let [r, g, b, _] = color.to_array();
+    let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+    let target = if luma >= 128.0 {
+        egui::Color32::WHITE
+    } else {
+        egui::Color32::BLACK
+    };
+    color.lerp_to_gamma(target, 0.6)
+}
+
+// Helper function: show a multiline text edit in an area of exactly `size`. Longer content
+// scrolls inside it instead of painting over neighboring nodes.
+fn scrolled_text_edit(
+    ui: &mut egui::Ui,
+    id_salt: impl Hash,
+    size: egui::Vec2,
+    text_edit: egui::TextEdit,
+) -> egui::Response {
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    ui.put(rect, |ui: &mut egui::Ui| {
+        egui::ScrollArea::vertical()
+            .id_salt(id_salt)
+            .auto_shrink(false)
+            .show(ui, |ui| ui.add_sized(size, text_edit))
+            .inner
+    })
+}
+
+// Helper function: nearest intersection of a grid with the given spacing to a canvas point.
+fn snap_to_grid(pos: egui::Pos2, spacing: f32) -> egui::Pos2 {
+    egui::pos2(
+        (pos.x / spacing).round() * spacing,
+        (pos.y / spacing).round() * spacing,
+    )
+}
+
+// Helper function: the two arrowheads of a measurement line, one at each end.
+fn measurement_heads(start: egui::Pos2, end: egui::Pos2, size: f32) -> [[egui::Pos2; 3]; 2] {
+    let dir = (end - start).normalized();
+    let perp = egui::vec2(-dir.y, dir.x) * size * 0.5;
+    [
+        [start + dir * size + perp, start, start + dir * size - perp],
+        [end - dir * size + perp, end, end - dir * size - perp],
+    ]
+}
+
+// Helper function: distance from `point` to the closest segment of a polyline.
+fn distance_to_polyline(points: &[egui::Pos2], point: egui::Pos2) -> f32 {
+    points
+        .windows(2)
+        .map(|segment| {
+            let (a, b) = (segment[0], segment[1]);
+            let ab = b - a;
+            let t = if ab.length_sq() > 0.0 {
+                ((point - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            (a + ab * t).distance(point)
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+// Helper function: smooth a freehand stroke by repeatedly moving each inner point halfway to
+// the midpoint of its neighbors. `strength` (0 to 1) sets the number of passes; the endpoints
+// stay put, and strokes of one or two points are returned unchanged.
+fn smooth_stroke(points: &[egui::Pos2], strength: f32) -> Vec<egui::Pos2> {
+    let mut smoothed = points.to_vec();
+    if smoothed.len() <= 2 {
+        return smoothed;
+    }
+    let passes = (strength.clamp(0.0, 1.0) * 5.0).round() as usize;
+    for _ in 0..passes {
+        let previous = smoothed.clone();
+        for (i, window) in previous.windows(3).enumerate() {
+            let midpoint = window[0].lerp(window[2], 0.5);
+            smoothed[i + 1] = window[1].lerp(midpoint, 0.5);
+        }
+    }
+    smoothed
+}
+
+// Helper function: width of the next point of a speed-dependent marker stroke, `distance`
+// canvas units after the previous one. Slow movement widens the line up to 1.5 times the
+// `thickness`, fast movement thins it down to half; easing from the previous width keeps
+// the changes gradual.
+fn speed_width(thickness: f32, previous: f32, distance: f32) -> f32 {
+    let speed = (distance / (4.0 * thickness.max(1.0))).min(1.0);
+    let target = thickness * (1.5 - speed);
+    previous + (target - previous) * 0.3
+}
+
+// Helper function: whether any segment of a polyline passes through `rect`, clipping each
+// segment against it (Liang–Barsky).
+fn polyline_intersects_rect(points: &[egui::Pos2], rect: egui::Rect) -> bool {
+    points.windows(2).any(|segment| {
+        let (a, b) = (segment[0], segment[1]);
+        let d = b - a;
+        let (mut t0, mut t1) = (0.0_f32, 1.0_f32);
+        for (p, q) in [
+            (-d.x, a.x - rect.min.x),
+            (d.x, rect.max.x - a.x),
+            (-d.y, a.y - rect.min.y),
+            (d.y, rect.max.y - a.y),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return false;
+                }
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+        t0 <= t1
+    })
+}
+
+// Helper function: pick the pair of sides through which two nodes face each other.
+fn facing_sides(start: egui::Rect, end: egui::Rect) -> (Side, Side) {
+    let d = end.center() - start.center();
+    if d.x.abs() >= d.y.abs() {
+        if d.x >= 0.0 {
+            (Side::Right, Side::Left)
+        } else {
+            (Side::Left, Side::Right)
+        }
+    } else if d.y >= 0.0 {
+        (Side::Bottom, Side::Top)
+    } else {
+        (Side::Top, Side::Bottom)
+    }
+}
+
+// Helper function: text escaped for XML content and attribute values.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Helper function: SVG paint attributes (`fill` or `stroke`) for a possibly translucent color.
+fn svg_paint(attribute: &str, color: egui::Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    if a == 255 {
+        format!("{}=\"#{:02x}{:02x}{:02x}\"", attribute, r, g, b)
+    } else {
+        format!(
+            "{}=\"#{:02x}{:02x}{:02x}\" {}-opacity=\"{:.3}\"",
+            attribute,
+            r,
+            g,
+            b,
+            attribute,
+            a as f32 / 255.0
+        )
+    }
+}
+
+// Helper function: an SVG rectangle, with a one unit border if `border` is set.
+fn svg_rect(rect: egui::Rect, fill: egui::Color32, border: Option<egui::Color32>) -> String {
+    let border = border
+        .map(|color| format!(" {} stroke-width=\"1\"", svg_paint("stroke", color)))
+        .unwrap_or_default();
+    format!(
+        "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" {}{}/>\n",
+        rect.min.x,
+        rect.min.y,
+        rect.width(),
+        rect.height(),
+        svg_paint("fill", fill),
+        border
+    )
+}
+
+// Helper function: an SVG image of the PNG file `bytes`, stretched over `rect`.
+fn svg_image(rect: egui::Rect, bytes: &[u8]) -> String {
+    format!(
+        "<image x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" \
+         preserveAspectRatio=\"none\" href=\"data:image/png;base64,{}\"/>\n",
+        rect.min.x,
+        rect.min.y,
+        rect.width(),
+        rect.height(),
+        base64::encode(bytes)
+    )
+}
+
+// Helper function: a nested SVG viewport at `rect`, which clips what is drawn inside it to
+// the rect. Must be closed with `</svg>`.
+fn svg_viewport(rect: egui::Rect) -> String {
+    format!(
+        "<svg x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\">\n",
+        rect.min.x,
+        rect.min.y,
+        rect.width(),
+        rect.height()
+    )
+}
+
+// Helper function: an SVG polyline with round joins.
+fn svg_polyline(points: &[egui::Pos2], width: f32, color: egui::Color32) -> String {
+    let points = points
+        .iter()
+        .map(|p| format!("{:.2},{:.2}", p.x, p.y))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "<polyline points=\"{}\" fill=\"none\" {} stroke-width=\"{:.2}\" \
+         stroke-linecap=\"round\" stroke-linejoin=\"round\"/>\n",
+        points,
+        svg_paint("stroke", color),
+        width
+    )
+}
+
+// Helper function: monospace SVG text, one line per text line. `centered` text is centered
+// on `pos`; otherwise `pos` is the top left corner of the first line.
+fn svg_text(
+    pos: egui::Pos2,
+    size: f32,
+    color: egui::Color32,
+    text: &str,
+    centered: bool,
+) -> String {
+    let line_height = size * 1.2;
+    let (anchor, top) = if centered {
+        let line_count = text.lines().count().max(1) as f32;
+        ("middle", pos.y - line_height * line_count / 2.0)
+    } else {
+        ("start", pos.y)
+    };
+    let mut svg = format!(
+        "<text font-family=\"monospace\" font-size=\"{:.2}\" text-anchor=\"{}\" \
+         xml:space=\"preserve\" {}>",
+        size,
+        anchor,
+        svg_paint("fill", color)
+    );
+    for (i, line) in text.lines().enumerate() {
+        svg += &format!(
+            "<tspan x=\"{:.2}\" y=\"{:.2}\">{}</tspan>",
+            pos.x,
+            top + line_height * (i as f32 + 0.8),
+            xml_escape(line)
+        );
+    }
+    svg += "</text>\n";
+    svg
+}
+
+// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Outcome of a connection CSV import: rows added, rows that could not be read, and rows
+// naming a node that isn't on the board.
+#[derive(Default)]
+struct CsvImportSummary {
+    added: usize,
+    malformed: usize,
+    missing_nodes: usize,
+}
+
+// Minimal CSV reader: comma separated, double-quoted fields may contain commas,
+// escaped quotes ("") and line breaks. Blank lines are ignored.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => record.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|f| !f.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    record.push(field);
+    if record.iter().any(|f| !f.is_empty()) {
+        records.push(record);
+    }
+    records
+}
+
+// Helper function: recovery file written next to a project file while it has unsaved changes.
+fn autosave_path(project_path: &std::path::Path) -> std::path::PathBuf {
+    let mut path = project_path.as_os_str().to_owned();
+    path.push(".autosave");
+    path.into()
+}
+
+// Helper function: the recovery file of a project, if it was written after the project.
+fn newer_autosave(project_path: &std::path::Path) -> Option<std::path::PathBuf> {
+    let autosave = autosave_path(project_path);
+    let modified = |path: &std::path::Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    (modified(&autosave)? > modified(project_path)?).then_some(autosave)
+}
+
+// Helper function: interaction id for a node. Note and code ids live in separate
+// namespaces keyed by node type, so they can never collide.
+fn node_widget_id(node_type: NodeType, node_id: usize) -> egui::Id {
+    egui::Id::new(("node", node_type, node_id))
+}
+
+// Helper function: given the list of connections, determine the index of the current connection
+// (i.e. its order among all arrows originating from the same node and side).
+fn get_arrow_index(
+    connections: &[NodeConnection],
+    node_type: NodeType,
+    node_id: usize,
+    side: Side,
+    current: &NodeConnection,
+) -> (usize, usize) {
+    let mut count = 0;
+    let mut index = 0;
+    for conn in connections {
+        if conn.start_node_type == node_type
+            && conn.start_node_id == node_id
+            && conn.start_side == side
+        {
+            if std::ptr::eq(conn, current) {
+                index = count;
+            }
+            count += 1;
+        }
+    }
+    (index, count)
+}
+
+impl App for MyApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
+        // eframe only autosaves while frames are running; make sure one comes up in time.
+        if self.preferences.autosave_enabled {
+            ctx.request_repaint_after(self.auto_save_interval());
+        }
+        // Synced code nodes: check their files for changes now and then.
+        if self.code_nodes.iter().any(|n| n.sync) {
+            if self
+                .last_code_sync
+                .is_none_or(|last| last.elapsed() >= CODE_SYNC_INTERVAL)
+            {
+                self.sync_code_nodes();
+                self.last_code_sync = Some(Instant::now());
+            }
+            ctx.request_repaint_after(CODE_SYNC_INTERVAL);
+        }
+        let input = ctx.input(|i| i.clone());
+        self.handle_shortcuts(ctx, &input);
+        // A nudging burst ends when no arrow key is held; it is undone as one step.
+        let arrow_held = ctx.input(|i| {
+            [
+                egui::Key::ArrowLeft,
+                egui::Key::ArrowRight,
+                egui::Key::ArrowUp,
+                egui::Key::ArrowDown,
+            ]
+            .into_iter()
+            .any(|key| i.key_down(key))
+        });
+        if !arrow_held {
+            if let Some(before) = self.nudge_start.take() {
+                self.push_undo(before);
+                self.redo_stack.clear();
+            }
+        }
+        // Files dropped from the file manager become code nodes where they were dropped.
+        let dropped_files = ctx.input(|i| i.raw.dropped_files.clone());
+        if !dropped_files.is_empty() {
+            let drop_pos = ctx
+                .input(|i| i.pointer.hover_pos())
+                .unwrap_or_else(|| ctx.screen_rect().center());
+            self.add_dropped_files(&dropped_files, self.to_canvas(drop_pos));
+        }
+        ctx.set_visuals(egui::Visuals {
+            code_bg_color: egui::Color32::from_rgb(32, 37, 43),
+            panel_fill: egui::Color32::from_rgb(40, 44, 52),
+            window_fill: egui::Color32::from_rgb(40, 44, 52),
+            faint_bg_color: egui::Color32::from_rgb(40, 44, 52),
+            extreme_bg_color: egui::Color32::from_rgb(40, 44, 52),
+            ..Default::default()
+        });
+        if self.show_log {
+            egui::TopBottomPanel::bottom("log")
+                .resizable(true)
+                .default_height(120.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.strong("Log");
+                        if ui.button("Clear").clicked() {
+                            self.log.clear();
+                        }
+                    });
+                    egui::ScrollArea::vertical()
+                        .auto_shrink(false)
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            for entry in &self.log {
+                                let color = match entry.level {
+                                    LogLevel::Info => ui.visuals().text_color(),
+                                    LogLevel::Warning => ui.visuals().warn_fg_color,
+                                    LogLevel::Error => ui.visuals().error_fg_color,
+                                };
+                                ui.label(
+                                    egui::RichText::new(format!(
+                                        "{} {}",
+                                        entry.time.format("%H:%M:%S"),
+                                        entry.message
+                                    ))
+                                    .monospace()
+                                    .color(color),
+                                );
+                            }
+                        });
+                });
+        }
+        // Inspector for the single selected node.
+        if let Some((node_type, id)) = self.selected_node {
+            egui::SidePanel::right("inspector")
+                .resizable(false)
+                .show(ctx, |ui| {
+                    self.node_inspector(ui, node_type, id);
+                });
+        }
+
+        // Canvas View
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // View Panning: dragging with the middle button, or with the left one while Space is
+            // held, pans with any tool active. The canvas, its tools and the nodes ignore the
+            // pointer meanwhile.
+            let space_held =
+                !ctx.wants_keyboard_input() && ctx.input(|i| i.key_down(egui::Key::Space));
+            let view_pan =
+                ctx.input(|i| i.pointer.middle_down() || (space_held && i.pointer.primary_down()));
+            if view_pan {
+                self.offset += ctx.input(|i| i.pointer.delta());
+                self.pan_velocity = egui::Vec2::ZERO;
+                ctx.set_cursor_icon(egui::CursorIcon::Grabbing);
+            } else if space_held {
+                ctx.set_cursor_icon(egui::CursorIcon::Grab);
+            }
+            let node_sense = if view_pan {
+                egui::Sense::hover()
+            } else {
+                egui::Sense::click_and_drag()
+            };
+
+            let response = ui.interact(
+                ui.max_rect(),
+                ui.id(),
+                if view_pan {
+                    egui::Sense::hover()
+                } else if self.active_tool != Tool::Arrow {
+                    egui::Sense::click_and_drag()
                 } else {
-                    let node = self
-                        .code_nodes
-                        .iter()
-                        .find(|n| n.id == connection.end_node_id)
-                        .unwrap_or(&fallback_code);
-                    (
-                        ((node.position * self.zoom) + self.offset),
-                        node.size * self.zoom,
-                    )
-                };
+                    egui::Sense::empty()
+                },
+            );
+
+            // Grid Drawing
+            let bounds = ui.clip_rect();
+            let painter = ui.painter_at(bounds);
+            let spacing = (self.grid_spacing * self.zoom).max(egui::vec2(1.0, 1.0));
+            let grid_color = egui::Color32::from_gray(60);
+            let top_left = bounds.left_top() - self.offset;
+            let bottom_right = bounds.right_bottom() - self.offset;
+            let columns = || grid_lines(top_left.x, bottom_right.x, spacing.x);
+            let rows = || grid_lines(top_left.y, bottom_right.y, spacing.y);
+            match self.grid_style {
+                GridStyle::Lines => {
+                    let stroke = egui::Stroke::new(1.0, grid_color);
+                    for x in columns() {
+                        painter.line_segment(
+                            [
+                                egui::pos2(x, top_left.y) + self.offset,
+                                egui::pos2(x, bottom_right.y) + self.offset,
+                            ],
+                            stroke,
+                        );
+                    }
+                    for y in rows() {
+                        painter.line_segment(
+                            [
+                                egui::pos2(top_left.x, y) + self.offset,
+                                egui::pos2(bottom_right.x, y) + self.offset,
+                            ],
+                            stroke,
+                        );
+                    }
+                }
+                GridStyle::Dots if spacing.min_elem() >= MIN_DOT_GRID_SPACING => {
+                    for x in columns() {
+                        for y in rows() {
+                            painter.circle_filled(egui::pos2(x, y) + self.offset, 1.5, grid_color);
+                        }
+                    }
+                }
+                GridStyle::Dots | GridStyle::None => {}
+            }
 
-                let (start_index, total_start) = get_arrow_index(
-                    &self.connections,
-                    connection.start_node_id,
-                    connection.start_side,
-                    connection,
+            // Origin Crosshair.
+            if self.show_origin {
+                let origin = egui::pos2(0.0, 0.0) + self.offset;
+                let axis_stroke = egui::Stroke::new(
+                    1.5,
+                    egui::Color32::from_rgba_unmultiplied(97, 175, 239, 110),
                 );
-                let start_connection_point = connection_point(
-                    start_pos,
-                    start_size,
-                    connection.start_side,
-                    start_index,
-                    total_start,
+                painter.line_segment(
+                    [
+                        egui::pos2(bounds.left(), origin.y),
+                        egui::pos2(bounds.right(), origin.y),
+                    ],
+                    axis_stroke,
                 );
-                let (end_index, total_end) = get_arrow_index(
-                    &self.connections,
-                    connection.end_node_id,
-                    connection.end_side,
-                    connection,
+                painter.line_segment(
+                    [
+                        egui::pos2(origin.x, bounds.top()),
+                        egui::pos2(origin.x, bounds.bottom()),
+                    ],
+                    axis_stroke,
                 );
-                let end_connection_point =
-                    connection_point(end_pos, end_size, connection.end_side, end_index, total_end);
+            }
 
-                let d = end_connection_point - start_connection_point;
-                let normal_start = side_normal(connection.start_side);
-                let normal_end = side_normal(connection.end_side);
-                let offset_distance = 50.0;
-                let control1 = start_connection_point + d * 0.3 + normal_start * offset_distance;
-                let control2 = start_connection_point + d * 0.7 + normal_end * offset_distance;
-                let bezier_points = compute_cubic_bezier_points(
-                    start_connection_point,
-                    control1,
-                    control2,
-                    end_connection_point,
-                    30,
-                );
-                for window in bezier_points.windows(2) {
+            // Highlighter strokes, under connections and nodes.
+            for stroke in self.strokes.iter().chain(&self.current_stroke) {
+                if stroke.kind == StrokeKind::Highlighter {
+                    self.paint_stroke(&painter, stroke);
+                }
+            }
+
+            // Render Connections. Arrows whose nodes are gone (orphaned) are skipped.
+            self.update_auto_sides();
+            let curves: Vec<Option<ConnectionCurve>> = self
+                .connections
+                .iter()
+                .map(|connection| self.connection_curve(connection, self.zoom, self.offset))
+                .collect();
+            let orphaned_connections = curves.iter().filter(|curve| curve.is_none()).count();
+            let hovered_connection = response
+                .hover_pos()
+                .and_then(|pointer| self.connection_at(pointer, &curves));
+            for (connection, curve) in self.connections.iter().zip(&curves) {
+                let Some(curve) = curve else {
+                    continue;
+                };
+                if self.selected_connections.contains(&connection.id) {
+                    painter.add(egui::Shape::line(
+                        curve.points.clone(),
+                        egui::Stroke::new(6.0, SELECTION_COLOR.gamma_multiply(0.5)),
+                    ));
+                } else if hovered_connection == Some(connection.id) {
+                    painter.add(egui::Shape::line(
+                        curve.points.clone(),
+                        egui::Stroke::new(4.0, SELECTION_COLOR.gamma_multiply(0.25)),
+                    ));
+                }
+                for window in curve.points.windows(2) {
                     if let [p1, p2] = window {
                         painter.line_segment([*p1, *p2], egui::Stroke::new(2.0, connection.color));
                     }
                 }
-                let arrow_head_size = 10.0;
-                let last_segment_dir = (end_connection_point - control2).normalized();
-                let perp = egui::vec2(-last_segment_dir.y, last_segment_dir.x);
-                let arrow_left = end_connection_point - last_segment_dir * arrow_head_size
-                    + perp * arrow_head_size * 0.5;
-                let arrow_right = end_connection_point
-                    - last_segment_dir * arrow_head_size
-                    - perp * arrow_head_size * 0.5;
-                painter.line_segment(
-                    [end_connection_point, arrow_left],
-                    egui::Stroke::new(2.0, connection.color),
-                );
-                painter.line_segment(
-                    [end_connection_point, arrow_right],
-                    egui::Stroke::new(2.0, connection.color),
-                );
+                if !connection.undirected {
+                    let (arrow_left, arrow_right) = curve.arrow_head(connection.arrow_head_size);
+                    painter.line_segment(
+                        [curve.end, arrow_left],
+                        egui::Stroke::new(2.0, connection.color),
+                    );
+                    painter.line_segment(
+                        [curve.end, arrow_right],
+                        egui::Stroke::new(2.0, connection.color),
+                    );
+                }
+                // Label centered on the curve, on a background so it stays readable over
+                // grid lines and other arrows.
+                if !connection.label.is_empty() {
+                    let galley = painter.layout_no_wrap(
+                        connection.label.clone(),
+                        egui::FontId::monospace(6.0 * self.zoom.min_elem()),
+                        ui.visuals().text_color(),
+                    );
+                    let label_rect =
+                        egui::Rect::from_center_size(curve.midpoint(), galley.size())
+                            .expand(2.0 * self.zoom.min_elem());
+                    painter.rect_filled(label_rect, 2.0 * self.zoom.min_elem(), ui.visuals().panel_fill);
+                    painter.galley(
+                        label_rect.min + egui::Vec2::splat(2.0 * self.zoom.min_elem()),
+                        galley,
+                        ui.visuals().text_color(),
+                    );
+                }
             }
 
-            // Temporary Arrow (in progress)
-            if self.arrow_connection_active {
-                if let Some((start_id, start_type, start_side)) = self.connection_start {
-                    let (start_pos, start_size) = if start_type == NodeType::Note {
-                        let node = self.note_nodes.iter().find(|n| n.id == start_id).unwrap();
-                        (
-                            ((node.position * self.zoom) + self.offset),
-                            node.size * self.zoom,
+            // Connection Selection: click an arrow to select it, Shift+click to add or remove it.
+            if self.active_tool == Tool::None && response.clicked() {
+                let hit = response
+                    .interact_pointer_pos()
+                    .and_then(|pointer| self.connection_at(pointer, &curves));
+                let shift = ctx.input(|i| i.modifiers.shift);
+                match hit {
+                    Some(id) => {
+                        if !shift {
+                            self.selected_connections.clear();
+                        }
+                        if !self.selected_connections.insert(id) {
+                            self.selected_connections.remove(&id);
+                        }
+                    }
+                    // A click on empty canvas closes all menus.
+                    None if !shift => self.deselect_all(),
+                    None => {}
+                }
+            }
+
+            // Context Menu: right-click an arrow to recolor or delete it, or empty canvas to
+            // create a node at that point.
+            if response.secondary_clicked() {
+                self.context_connection = hovered_connection;
+                self.context_canvas_pos = response.interact_pointer_pos().map(|p| self.to_canvas(p));
+            }
+            let mut delete_connection = None;
+            let mut recolor_connection = None;
+            let mut new_node = None;
+            let mut paste = false;
+            let mut paste_image = false;
+            let context_color = self
+                .context_connection
+                .and_then(|id| self.connections.iter().find(|c| c.id == id))
+                .map(|conn| conn.color);
+            response.context_menu(|ui| match (self.context_connection, context_color) {
+                (Some(id), Some(mut color)) => {
+                    // The picker is shown inline: a popup would count as a click outside the
+                    // menu and close it.
+                    if egui::color_picker::color_picker_color32(
+                        ui,
+                        &mut color,
+                        egui::color_picker::Alpha::OnlyBlend,
+                    ) {
+                        recolor_connection = Some((id, color));
+                    }
+                    if ui.button("Delete Connection").clicked() {
+                        delete_connection = Some(id);
+                        ui.close_menu();
+                    }
+                }
+                _ => {
+                    if ui.button("New Note Here").clicked() {
+                        new_node = Some(NodeType::Note);
+                        ui.close_menu();
+                    }
+                    if ui.button("New Code Node Here").clicked() {
+                        new_node = Some(NodeType::Code);
+                        ui.close_menu();
+                    }
+                    if ui
+                        .button("Paste")
+                        .on_hover_text("New note with the clipboard text")
+                        .clicked()
+                    {
+                        paste = true;
+                        ui.close_menu();
+                    }
+                    if ui
+                        .button("Paste Image")
+                        .on_hover_text("New image node with the clipboard image")
+                        .clicked()
+                    {
+                        paste_image = true;
+                        ui.close_menu();
+                    }
+                }
+            });
+            if let Some(position) = self.context_canvas_pos {
+                match new_node {
+                    Some(NodeType::Note) => {
+                        let id = self.add_note_at(position);
+                        self.edit_request = Some((NodeType::Note, id));
+                    }
+                    Some(NodeType::Code) if self.ensure_project_root() => {
+                        let id = self.add_code_at(position);
+                        self.edit_request = Some((NodeType::Code, id));
+                    }
+                    _ => {}
+                }
+                if paste {
+                    if let Err(e) = self.paste_note_at(position) {
+                        self.log_error(format!("Paste error: {}", e));
+                    }
+                }
+                if paste_image {
+                    if let Err(e) = self.paste_image_at(position) {
+                        self.log_error(format!("Paste error: {}", e));
+                    }
+                }
+            }
+            if let Some((id, color)) = recolor_connection {
+                self.record_state_coalesced();
+                if let Some(conn) = self.connections.iter_mut().find(|c| c.id == id) {
+                    conn.color = color;
+                }
+            }
+            if let Some(id) = delete_connection {
+                self.record_state();
+                self.connections.retain(|conn| conn.id != id);
+                self.context_connection = None;
+            }
+            self.selected_connections
+                .retain(|id| self.connections.iter().any(|c| c.id == *id));
+
+            // Floating menu for the selected connections, anchored at the first one's midpoint.
+            let menu_anchor = self
+                .connections
+                .iter()
+                .zip(&curves)
+                .filter(|(conn, _)| self.selected_connections.contains(&conn.id))
+                .find_map(|(conn, curve)| {
+                    Some((
+                        conn.color,
+                        conn.undirected,
+                        conn.auto_sides,
+                        curve.as_ref()?.midpoint(),
+                    ))
+                });
+            if let Some((first_color, first_undirected, first_auto_sides, anchor)) = menu_anchor {
+                let screen_rect = ctx.screen_rect();
+                let anchor_rect = egui::Rect::from_center_size(anchor, egui::Vec2::ZERO);
+                let mut recolor = None;
+                let mut set_undirected = None;
+                let mut set_auto_sides = None;
+                let mut reset_curve = false;
+                let mut to_remove = false;
+                egui::Area::new("connection_menu".into())
+                    .fixed_pos(floating_menu_pos(anchor_rect, screen_rect))
+                    .constrain_to(screen_rect)
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            let count = self.selected_connections.len();
+                            if count > 1 {
+                                ui.label(format!("{} arrows", count));
+                            }
+                            let mut color = first_color;
+                            if egui::color_picker::color_edit_button_srgba(
+                                ui,
+                                &mut color,
+                                egui::color_picker::Alpha::OnlyBlend,
+                            )
+                            .changed()
+                            {
+                                recolor = Some(color);
+                            }
+                            let mut undirected = first_undirected;
+                            if ui
+                                .checkbox(&mut undirected, "Undirected")
+                                .on_hover_text("Draw without an arrowhead")
+                                .changed()
+                            {
+                                set_undirected = Some(undirected);
+                            }
+                            let mut auto_sides = first_auto_sides;
+                            if ui
+                                .checkbox(&mut auto_sides, "Auto sides")
+                                .on_hover_text(
+                                    "Leave from the sides facing each other as the nodes move",
+                                )
+                                .changed()
+                            {
+                                set_auto_sides = Some(auto_sides);
+                            }
+                            if ui
+                                .button("Reset Curve")
+                                .on_hover_text("Drop manual routing and the curve offset")
+                                .clicked()
+                            {
+                                reset_curve = true;
+                            }
+                            if ui.button("Delete").clicked() {
+                                to_remove = true;
+                            }
+                        });
+                    });
+                if let Some(color) = recolor {
+                    self.record_state_coalesced();
+                    for conn in &mut self.connections {
+                        if self.selected_connections.contains(&conn.id) {
+                            conn.color = color;
+                        }
+                    }
+                }
+                if let Some(undirected) = set_undirected {
+                    self.record_state();
+                    for conn in &mut self.connections {
+                        if self.selected_connections.contains(&conn.id) {
+                            conn.undirected = undirected;
+                        }
+                    }
+                }
+                if let Some(auto_sides) = set_auto_sides {
+                    self.record_state();
+                    for conn in &mut self.connections {
+                        if self.selected_connections.contains(&conn.id) {
+                            conn.auto_sides = auto_sides;
+                        }
+                    }
+                }
+                if reset_curve {
+                    self.record_state();
+                    for conn in &mut self.connections {
+                        if self.selected_connections.contains(&conn.id) {
+                            conn.control_points = None;
+                            conn.offset_distance = default_offset_distance();
+                        }
+                    }
+                }
+                if to_remove {
+                    self.record_state();
+                    self.connections
+                        .retain(|conn| !self.selected_connections.contains(&conn.id));
+                    self.selected_connections.clear();
+                }
+            }
+
+            // Label Editor: a text field on the connection's midpoint, opened by double-clicking
+            // it. Enter or clicking away applies the label, Escape discards it.
+            let label_anchor = self.label_edit.as_ref().and_then(|(id, _)| {
+                self.connections
+                    .iter()
+                    .zip(&curves)
+                    .find(|(conn, _)| conn.id == *id)
+                    .and_then(|(_, curve)| Some(curve.as_ref()?.midpoint()))
+            });
+            // If the connection is gone, the edit is dropped.
+            if let (Some(anchor), Some((id, mut text))) = (label_anchor, self.label_edit.take()) {
+                let text_id = egui::Id::new(("connection_label", id));
+                let response = egui::Area::new("label_edit".into())
+                    .fixed_pos(anchor)
+                    .pivot(egui::Align2::CENTER_CENTER)
+                    .show(ctx, |ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut text)
+                                .id(text_id)
+                                .hint_text("Label")
+                                .desired_width(120.0),
                         )
-                    } else {
-                        let node = self.code_nodes.iter().find(|n| n.id == start_id).unwrap();
-                        (
-                            ((node.position * self.zoom) + self.offset),
-                            node.size * self.zoom,
+                    })
+                    .inner;
+                if !response.lost_focus() {
+                    if !ctx.memory(|mem| mem.had_focus_last_frame(text_id)) {
+                        response.request_focus();
+                    }
+                    self.label_edit = Some((id, text));
+                } else if !ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    let changed = self
+                        .connections
+                        .iter()
+                        .any(|conn| conn.id == id && conn.label != text);
+                    if changed {
+                        self.record_state();
+                        if let Some(conn) = self.connections.iter_mut().find(|c| c.id == id) {
+                            conn.label = text;
+                        }
+                    }
+                }
+            }
+
+            // Connection Handles: with a single arrow selected, drag the square on the curve to
+            // change how far it bows out, the diamonds to move its control points and the circle to
+            // resize the arrowhead. The square is hidden once the control points are set by hand.
+            let single_selected = match self.selected_connections.len() {
+                1 => self.selected_connections.iter().next().copied(),
+                _ => None,
+            };
+            let handle_target = single_selected
+                .and_then(|id| self.connections.iter().position(|c| c.id == id))
+                .and_then(|index| {
+                    let curve =
+                        self.connection_curve(&self.connections[index], self.zoom, self.offset)?;
+                    Some((index, curve))
+                });
+            if let Some((index, curve)) = handle_target {
+                let start = curve.points[0];
+                let offset_handle = curve.offset_handle();
+                // How far the square moves per unit of `offset_distance`, found by bowing the
+                // curve one unit further, so drags can be turned back into a distance.
+                let offset_step = {
+                    let original = self.connections[index].offset_distance;
+                    self.connections[index].offset_distance = original + 1.0;
+                    let bowed =
+                        self.connection_curve(&self.connections[index], self.zoom, self.offset);
+                    self.connections[index].offset_distance = original;
+                    bowed
+                        .map(|bowed| bowed.offset_handle() - offset_handle)
+                        .filter(|step| step.length_sq() > 1e-6)
+                        .unwrap_or_else(|| side_normal(self.connections[index].start_side))
+                };
+                let conn = &self.connections[index];
+                let arrow_handle = curve.end - curve.end_direction() * conn.arrow_head_size;
+                let handle_size = egui::Vec2::splat(10.0);
+                let offset_response = conn.control_points.is_none().then(|| {
+                    ui.interact(
+                        egui::Rect::from_center_size(offset_handle, handle_size),
+                        ui.id().with(("curve_handle", conn.id)),
+                        egui::Sense::drag(),
+                    )
+                });
+                let control_responses = [curve.control1, curve.control2]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(k, point)| {
+                        ui.interact(
+                            egui::Rect::from_center_size(point, handle_size),
+                            ui.id().with(("control_handle", conn.id, k)),
+                            egui::Sense::drag(),
                         )
-                    };
+                    })
+                    .collect::<Vec<_>>();
+                let arrow_response = (!conn.undirected).then(|| {
+                    ui.interact(
+                        egui::Rect::from_center_size(arrow_handle, handle_size),
+                        ui.id().with(("arrow_handle", conn.id)),
+                        egui::Sense::drag(),
+                    )
+                });
+                let handle_stroke = egui::Stroke::new(1.5, SELECTION_COLOR);
+                if offset_response.is_some() {
+                    painter.rect_filled(
+                        egui::Rect::from_center_size(offset_handle, egui::Vec2::splat(8.0)),
+                        1.0,
+                        SELECTION_COLOR,
+                    );
+                }
+                for (anchor, point) in [(start, curve.control1), (curve.end, curve.control2)] {
+                    painter.line_segment([anchor, point], handle_stroke);
+                    let diamond = [
+                        point + egui::vec2(0.0, -5.0),
+                        point + egui::vec2(5.0, 0.0),
+                        point + egui::vec2(0.0, 5.0),
+                        point + egui::vec2(-5.0, 0.0),
+                    ];
+                    painter.add(egui::Shape::convex_polygon(
+                        diamond.to_vec(),
+                        SELECTION_COLOR,
+                        egui::Stroke::NONE,
+                    ));
+                }
+                if arrow_response.is_some() {
+                    painter.circle_filled(arrow_handle, 4.0, SELECTION_COLOR);
+                }
+                let offset_drag_started = offset_response.as_ref().is_some_and(|r| r.drag_started());
+                let arrow_drag_started = arrow_response.as_ref().is_some_and(|r| r.drag_started());
+                let control_drag_started = control_responses.iter().any(|r| r.drag_started());
+                if offset_drag_started || arrow_drag_started || control_drag_started {
+                    self.record_state();
+                }
+                let direction = curve.end_direction();
+                let zoom = self.zoom;
+                let generated = (self.to_canvas(curve.control1), self.to_canvas(curve.control2));
+                let conn = &mut self.connections[index];
+                if let Some(response) = offset_response.filter(|r| r.dragged()) {
+                    conn.offset_distance = (conn.offset_distance
+                        + response.drag_delta().dot(offset_step) / offset_step.length_sq())
+                    .clamp(0.0, 400.0);
+                }
+                if control_responses.iter().any(|r| r.dragged()) {
+                    // The first drag turns the generated curve into manual control points.
+                    let (c1, c2) = conn.control_points.get_or_insert(generated);
+                    *c1 += control_responses[0].drag_delta() / zoom;
+                    *c2 += control_responses[1].drag_delta() / zoom;
+                }
+                if let Some(response) = arrow_response.filter(|r| r.dragged()) {
+                    // Dragging away from the tip makes the arrowhead longer.
+                    conn.arrow_head_size =
+                        (conn.arrow_head_size - response.drag_delta().dot(direction)).clamp(4.0, 40.0);
+                }
+            }
+
+            // Temporary Arrow (in progress)
+            if self.active_tool == Tool::Arrow {
+                let start = self.connection_start.and_then(|(id, node_type, side)| {
+                    Some((self.node_rect(node_type, id)?, side))
+                });
+                if let Some((start_rect, start_side)) = start {
+                    let start_pos = self.to_screen(start_rect.min);
+                    let start_size = start_rect.size() * self.zoom;
                     let start_connection_point =
                         connection_point(start_pos, start_size, start_side, 0, 1);
                     if let Some(pointer_pos) = ctx.input(|i| i.pointer.interact_pos()) {
                         let d = pointer_pos - start_connection_point;
                         let normal_start = side_normal(start_side);
-                        let offset_distance = 50.0;
+                        let offset_distance = default_offset_distance();
                         let control1 =
                             start_connection_point + d * 0.3 + normal_start * offset_distance;
                         let control2 =
@@ -651,95 +5060,258 @@ impl App for MyApp {
             // Marker and Eraser Drawing.
             let pointer = ctx.input(|i| i.pointer.clone());
 
-            // Use flags to record only once after the operation.
-            static mut MARKER_STATE_RECORDED: bool = false;
-            static mut ERASER_STATE_RECORDED: bool = false;
-
-            if self.marker_active {
-                if pointer.primary_down() {
-                    // Reset the flag while drawing.
-                    unsafe {
-                        MARKER_STATE_RECORDED = false;
-                    }
+            let drawing = match self.active_tool {
+                Tool::Marker => Some((StrokeKind::Marker, self.marker_color, self.marker_thickness)),
+                Tool::Highlighter => Some((
+                    StrokeKind::Highlighter,
+                    self.highlighter_color,
+                    self.highlighter_thickness,
+                )),
+                _ => None,
+            };
+            if let Some((kind, color, thickness)) = drawing {
+                let pointer_down = pointer.primary_down() && !view_pan;
+                self.finish_stroke(pointer_down);
+                if pointer_down {
                     if let Some(pos) = pointer.interact_pos() {
-                        let canvas_pos = (pos - self.offset) / self.zoom;
+                        let canvas_pos = self.to_canvas(pos);
                         if let Some(stroke) = self.current_stroke.as_mut() {
+                            if let (Some(last), Some(&last_width)) =
+                                (stroke.points.last(), stroke.widths.last())
+                            {
+                                stroke.widths.push(speed_width(
+                                    thickness,
+                                    last_width,
+                                    last.distance(canvas_pos),
+                                ));
+                            }
                             stroke.points.push(canvas_pos);
-                        } else {
+                        } else if ctx
+                            .layer_id_at(pos)
+                            .is_none_or(|layer| layer.order == egui::Order::Background)
+                        {
+                            // Only start strokes on the canvas, not in the tools overlay (e.g.
+                            // while picking the marker color).
+                            let speed_widths =
+                                kind == StrokeKind::Marker && self.marker_speed_width;
                             self.current_stroke = Some(Stroke {
                                 points: vec![canvas_pos],
-                                color: egui::Color32::from_rgb(187, 192, 206),
-                                thickness: 2.0,
+                                color,
+                                thickness,
+                                kind,
+                                widths: if speed_widths { vec![thickness] } else { Vec::new() },
                             });
                         }
                     }
-                } else if let Some(stroke) = self.current_stroke.take() {
-                    self.strokes.push(stroke);
-                    // Only record state once when the pointer is released.
-                    unsafe {
-                        if !MARKER_STATE_RECORDED {
-                            self.record_state();
-                            MARKER_STATE_RECORDED = true;
-                        }
-                    }
                 }
             }
 
-            if self.eraser_active {
-                if pointer.primary_down() {
-                    // Reset the flag while erasing.
-                    unsafe {
-                        ERASER_STATE_RECORDED = false;
-                    }
+            if self.active_tool == Tool::Eraser {
+                let pointer_down = pointer.primary_down() && !view_pan;
+                if pointer_down {
                     if let Some(pos) = pointer.interact_pos() {
-                        let canvas_pos = (pos - self.offset) / self.zoom;
-                        let threshold = 10.0 / self.zoom;
-                        for stroke in &mut self.strokes {
-                            stroke
-                                .points
-                                .retain(|&p| p.distance(canvas_pos) >= threshold);
+                        // Compare in screen space, where the eraser is round even when the
+                        // zoom isn't uniform.
+                        let threshold = 10.0;
+                        let (zoom, offset) = (self.zoom, self.offset);
+                        let to_screen = |p: egui::Pos2| (p.to_vec2() * zoom).to_pos2() + offset;
+                        let near = |p: &egui::Pos2| to_screen(*p).distance(pos) < threshold;
+                        let measurement_near = |m: &Measurement| {
+                            distance_to_polyline(&[to_screen(m.start), to_screen(m.end)], pos)
+                                < threshold
+                        };
+                        let shape_near = |shape: &Shape| {
+                            let outline: Vec<egui::Pos2> =
+                                shape.outline().into_iter().map(to_screen).collect();
+                            distance_to_polyline(&outline, pos) < threshold
+                        };
+                        let hit = self.strokes.iter().any(|s| s.points.iter().any(near))
+                            || self.measurements.iter().any(measurement_near)
+                            || self.shapes.iter().any(shape_near);
+                        self.record_erase(pointer_down, hit);
+                        if hit {
+                            if self.stroke_eraser {
+                                self.strokes.retain(|s| !s.points.iter().any(near));
+                            } else {
+                                for stroke in &mut self.strokes {
+                                    stroke.retain_points(|p| !near(p));
+                                }
+                                self.strokes.retain(|s| s.points.len() > 1);
+                            }
+                            self.measurements.retain(|m| !measurement_near(m));
+                            self.shapes.retain(|shape| !shape_near(shape));
                         }
-                        self.strokes.retain(|s| s.points.len() > 1);
                     }
                 } else {
-                    // When pointer is released, record state if it hasn't been recorded yet.
-                    unsafe {
-                        if !ERASER_STATE_RECORDED {
+                    self.record_erase(false, false);
+                }
+            }
+
+            // Draw Strokes.
+            let stroke_count = self.strokes.len();
+            self.selected_strokes.retain(|index| *index < stroke_count);
+            for stroke in self.selected_strokes.iter().map(|index| &self.strokes[*index]) {
+                let points: Vec<egui::Pos2> = stroke
+                    .points
+                    .iter()
+                    .map(|p| self.to_screen(*p))
+                    .collect();
+                painter.add(egui::Shape::line(
+                    points,
+                    egui::Stroke::new(
+                        stroke.thickness * self.zoom.min_elem() + 4.0,
+                        SELECTION_COLOR.gamma_multiply(0.5),
+                    ),
+                ));
+            }
+            for stroke in self.strokes.iter().chain(&self.current_stroke) {
+                if stroke.kind == StrokeKind::Marker {
+                    self.paint_stroke(&painter, stroke);
+                }
+            }
+
+            // Shape Tools: drag out a line, rectangle or ellipse, snapped to the grid if enabled.
+            // Shift makes it a 45° line, square or circle.
+            if let Some(kind) = match self.active_tool {
+                Tool::Line => Some(ShapeKind::Line),
+                Tool::Rect => Some(ShapeKind::Rect),
+                Tool::Ellipse => Some(ShapeKind::Ellipse),
+                _ => None,
+            } {
+                let pointer_canvas = response
+                    .interact_pointer_pos()
+                    .map(|pos| self.snapped(self.to_canvas(pos)));
+                if let (true, Some(pos)) = (response.drag_started(), pointer_canvas) {
+                    self.current_shape = Some(Shape {
+                        kind,
+                        start: pos,
+                        end: pos,
+                        color: egui::Color32::from_rgb(187, 192, 206),
+                        thickness: 2.0,
+                    });
+                }
+                let constrain = ctx.input(|i| i.modifiers.shift);
+                let snap = self.snap_to_grid.then_some(self.grid_spacing);
+                if let (Some(shape), Some(pos)) = (self.current_shape.as_mut(), pointer_canvas) {
+                    shape.end = shape_end(shape.kind, shape.start, pos, constrain, snap);
+                }
+                if response.drag_stopped() {
+                    if let Some(shape) = self.current_shape.take() {
+                        if shape.start != shape.end {
                             self.record_state();
-                            ERASER_STATE_RECORDED = true;
+                            self.shapes.push(shape);
+                        }
+                    }
+                }
+            } else {
+                self.current_shape = None;
+            }
+
+            // Draw Shapes, including the one being dragged out.
+            for shape in self.shapes.iter().chain(&self.current_shape) {
+                let points: Vec<egui::Pos2> = shape
+                    .outline()
+                    .into_iter()
+                    .map(|p| self.to_screen(p))
+                    .collect();
+                painter.add(egui::Shape::line(
+                    points,
+                    egui::Stroke::new(shape.thickness * self.zoom.min_elem(), shape.color),
+                ));
+            }
+
+            // Draw Measurements, plus the one being placed (from its start to the pointer).
+            let pending_measurement = self
+                .measure_start
+                .zip(ctx.input(|i| i.pointer.hover_pos()))
+                .map(|(start, pointer)| Measurement {
+                    start,
+                    end: self.to_canvas(pointer),
+                });
+            for measurement in self.measurements.iter().chain(&pending_measurement) {
+                let start = self.to_screen(measurement.start);
+                let end = self.to_screen(measurement.end);
+                let line_stroke = egui::Stroke::new(1.5, MEASURE_COLOR);
+                painter.line_segment([start, end], line_stroke);
+                for head in measurement_heads(start, end, 8.0) {
+                    painter.add(egui::Shape::line(head.to_vec(), line_stroke));
+                }
+                let galley = painter.layout_no_wrap(
+                    format!("{:.0}", measurement.length()),
+                    egui::FontId::monospace(6.0 * self.zoom.min_elem()),
+                    MEASURE_COLOR,
+                );
+                let label_rect =
+                    egui::Rect::from_center_size(egui::Rect::from_two_pos(start, end).center(), galley.size())
+                        .expand(2.0 * self.zoom.min_elem());
+                painter.rect_filled(label_rect, 2.0 * self.zoom.min_elem(), ui.visuals().panel_fill);
+                painter.galley(
+                    label_rect.min + egui::Vec2::splat(2.0 * self.zoom.min_elem()),
+                    galley,
+                    MEASURE_COLOR,
+                );
+            }
+            if pending_measurement.is_some() {
+                ctx.request_repaint();
+            }
+
+            // Selected strokes: drag their bounding box to move them together, or delete them.
+            if let Some(canvas_bounds) = self
+                .selected_strokes
+                .iter()
+                .map(|index| self.strokes[*index].bounds())
+                .reduce(|a, b| a.union(b))
+            {
+                let bounds = egui::Rect::from_min_max(
+                    self.to_screen(canvas_bounds.min),
+                    self.to_screen(canvas_bounds.max),
+                )
+                .expand(4.0);
+                painter.rect_stroke(
+                    bounds,
+                    2.0,
+                    egui::Stroke::new(1.0, SELECTION_COLOR),
+                    egui::StrokeKind::Outside,
+                );
+                let move_response = ui.interact(bounds, ui.id().with("stroke_group"), egui::Sense::drag());
+                if move_response.drag_started() {
+                    self.record_state();
+                }
+                if move_response.dragged() {
+                    let delta = move_response.drag_delta() / self.zoom;
+                    for index in &self.selected_strokes {
+                        for point in &mut self.strokes[*index].points {
+                            *point += delta;
                         }
                     }
                 }
-            }
-
-            // Draw Strokes.
-            for stroke in &self.strokes {
-                for window in stroke.points.windows(2) {
-                    if let [a, b] = window {
-                        let a = (*a) * self.zoom + self.offset;
-                        let b = (*b) * self.zoom + self.offset;
-                        painter.line_segment(
-                            [a, b],
-                            egui::Stroke::new(stroke.thickness * self.zoom, stroke.color),
-                        );
-                    }
+                if move_response.hovered() || move_response.dragged() {
+                    ctx.set_cursor_icon(egui::CursorIcon::Move);
                 }
-            }
-            if let Some(stroke) = &self.current_stroke {
-                for window in stroke.points.windows(2) {
-                    if let [a, b] = window {
-                        let a = (*a) * self.zoom + self.offset;
-                        let b = (*b) * self.zoom + self.offset;
-                        painter.line_segment(
-                            [a, b],
-                            egui::Stroke::new(stroke.thickness * self.zoom, stroke.color),
-                        );
+
+                let screen_rect = ctx.screen_rect();
+                let mut to_remove = false;
+                egui::Area::new("stroke_menu".into())
+                    .fixed_pos(floating_menu_pos(bounds, screen_rect))
+                    .constrain_to(screen_rect)
+                    .show(ctx, |ui| {
+                        if ui.button("Delete").clicked() {
+                            to_remove = true;
+                        }
+                    });
+                if to_remove {
+                    self.record_state();
+                    let mut indices = std::mem::take(&mut self.selected_strokes);
+                    indices.sort_unstable_by(|a, b| b.cmp(a));
+                    indices.dedup();
+                    for index in indices {
+                        self.strokes.remove(index);
                     }
                 }
             }
 
             // Arrow Connection Logic.
-            if self.arrow_connection_active {
+            if self.active_tool == Tool::Arrow {
                 // Helper function to determine closest side of a node.
                 fn determine_closest_side(
                     node_pos: egui::Pos2,
@@ -775,84 +5347,164 @@ impl App for MyApp {
                     }
                 }
 
+                // Connection logic for image nodes, first since they're drawn underneath.
+                for i in 0..self.image_nodes.len() {
+                    let node = &self.image_nodes[i]; // immutable borrow
+                    let node_id = node.id;
+                    let scaled_position = self.to_screen(node.position);
+                    let scaled_size = node.size * self.zoom;
+                    let rect = egui::Rect::from_min_size(scaled_position, scaled_size);
+                    let response = ui.interact(
+                        rect,
+                        node_widget_id(NodeType::Image, node_id),
+                        egui::Sense::click(),
+                    );
+                    if response.clicked() {
+                        let pointer_pos = response.interact_pointer_pos().unwrap();
+                        let closest_side =
+                            determine_closest_side(scaled_position, scaled_size, pointer_pos);
+                        if self.connection_start.is_some() {
+                            self.connect_to(NodeType::Image, node_id, closest_side);
+                        } else {
+                            self.connection_start = Some((node_id, NodeType::Image, closest_side));
+                        }
+                    }
+                }
                 // Connection logic for note nodes.
                 for i in 0..self.note_nodes.len() {
                     let note = &self.note_nodes[i]; // immutable borrow
-                    let scaled_position = (note.position * self.zoom) + self.offset;
+                    let note_id = note.id;
+                    let scaled_position = self.to_screen(note.position);
                     let scaled_size = note.size * self.zoom;
                     let rect = egui::Rect::from_min_size(scaled_position, scaled_size);
-                    let response =
-                        ui.interact(rect, ui.make_persistent_id(note.id), egui::Sense::click());
+                    let response = ui.interact(
+                        rect,
+                        node_widget_id(NodeType::Note, note_id),
+                        egui::Sense::click(),
+                    );
                     if response.clicked() {
                         // Capture local values.
                         let pointer_pos = response.interact_pointer_pos().unwrap();
-                        if let Some((start_id, start_type, start_side)) = self.connection_start {
-                            let end_side =
-                                determine_closest_side(scaled_position, scaled_size, pointer_pos);
-                            self.connections.push(NodeConnection {
-                                start_node_id: start_id,
-                                start_node_type: start_type,
-                                start_side,
-                                end_node_id: note.id,
-                                end_node_type: NodeType::Note,
-                                end_side,
-                                control_points: None,
-                                color: egui::Color32::from_rgb(187, 192, 206),
-                            });
-                            self.connection_start = None;
-                            self.record_state(); // Record state after creating a connection.
+                        let closest_side =
+                            determine_closest_side(scaled_position, scaled_size, pointer_pos);
+                        if self.connection_start.is_some() {
+                            self.connect_to(NodeType::Note, note_id, closest_side);
                         } else {
-                            let closest_side =
-                                determine_closest_side(scaled_position, scaled_size, pointer_pos);
-                            self.connection_start = Some((note.id, NodeType::Note, closest_side));
+                            self.connection_start = Some((note_id, NodeType::Note, closest_side));
                         }
                     }
                 }
                 // Connection logic for code nodes.
                 for i in 0..self.code_nodes.len() {
                     let node = &self.code_nodes[i]; // immutable borrow
-                    let scaled_position = (node.position * self.zoom) + self.offset;
+                    let node_id = node.id;
+                    let scaled_position = self.to_screen(node.position);
                     let scaled_size = node.size * self.zoom;
                     let rect = egui::Rect::from_min_size(scaled_position, scaled_size);
                     let response = ui.interact(
                         rect,
-                        ui.make_persistent_id(node.id + 10_000),
+                        node_widget_id(NodeType::Code, node_id),
                         egui::Sense::click(),
                     );
                     if response.clicked() {
                         let pointer_pos = response.interact_pointer_pos().unwrap();
-                        if let Some((start_id, start_type, start_side)) = self.connection_start {
-                            let end_side =
-                                determine_closest_side(scaled_position, scaled_size, pointer_pos);
-                            self.connections.push(NodeConnection {
-                                start_node_id: start_id,
-                                start_node_type: start_type,
-                                start_side,
-                                end_node_id: node.id,
-                                end_node_type: NodeType::Code,
-                                end_side,
-                                control_points: None,
-                                color: egui::Color32::from_rgb(187, 192, 206),
-                            });
-                            self.connection_start = None;
-                            self.record_state(); // Record state after connection creation.
+                        let closest_side =
+                            determine_closest_side(scaled_position, scaled_size, pointer_pos);
+                        if self.connection_start.is_some() {
+                            self.connect_to(NodeType::Code, node_id, closest_side);
                         } else {
-                            let closest_side =
-                                determine_closest_side(scaled_position, scaled_size, pointer_pos);
-                            self.connection_start = Some((node.id, NodeType::Code, closest_side));
+                            self.connection_start = Some((node_id, NodeType::Code, closest_side));
+                        }
+                    }
+                }
+            }
+
+            let mut region_active = false;
+            // Region Selection: with the Select tool, or Shift held without a tool, dragging on
+            // the canvas selects the nodes and connections the dragged region touches and the
+            // strokes whose bounding box lies entirely inside it.
+            let shift = ctx.input(|i| i.modifiers.shift);
+            if matches!(self.active_tool, Tool::Select | Tool::None) {
+                if response.drag_started() && (self.active_tool == Tool::Select || shift) {
+                    self.region_start = response.interact_pointer_pos().map(|pos| self.to_canvas(pos));
+                }
+                // A region drag replaces panning.
+                region_active = self.region_start.is_some();
+                if let (Some(start), Some(pointer)) = (self.region_start, response.interact_pointer_pos()) {
+                    let region = egui::Rect::from_two_pos(start, self.to_canvas(pointer));
+                    if response.drag_stopped() {
+                        if !shift {
+                            self.selected_strokes.clear();
+                            self.selected_nodes.clear();
+                            self.selected_connections.clear();
+                        }
+                        let screen_region = egui::Rect::from_two_pos(self.to_screen(start), pointer);
+                        for (connection, curve) in self.connections.iter().zip(&curves) {
+                            if curve
+                                .as_ref()
+                                .is_some_and(|c| polyline_intersects_rect(&c.points, screen_region))
+                            {
+                                self.selected_connections.insert(connection.id);
+                            }
+                        }
+                        for (index, stroke) in self.strokes.iter().enumerate() {
+                            if region.contains_rect(stroke.bounds())
+                                && !self.selected_strokes.contains(&index)
+                            {
+                                self.selected_strokes.push(index);
+                            }
+                        }
+                        let touched: Vec<(NodeType, usize)> = self
+                            .note_nodes
+                            .iter()
+                            .map(|n| (NodeType::Note, n.id))
+                            .chain(self.code_nodes.iter().map(|n| (NodeType::Code, n.id)))
+                            .chain(self.image_nodes.iter().map(|n| (NodeType::Image, n.id)))
+                            .filter(|(node_type, id)| {
+                                self.node_rect(*node_type, *id)
+                                    .is_some_and(|rect| rect.intersects(region))
+                            })
+                            .collect();
+                        for key in touched {
+                            if !self.selected_nodes.contains(&key) {
+                                self.selected_nodes.push(key);
+                            }
                         }
+                        self.selected_node = match self.selected_nodes.as_slice() {
+                            &[key] => Some(key),
+                            _ => None,
+                        };
+                        self.region_start = None;
+                    } else {
+                        let screen_region = egui::Rect::from_two_pos(self.to_screen(start), pointer);
+                        painter.rect(
+                            screen_region,
+                            0.0,
+                            SELECTION_COLOR.gamma_multiply(0.1),
+                            egui::Stroke::new(1.0, SELECTION_COLOR),
+                            egui::StrokeKind::Inside,
+                        );
                     }
                 }
+                if !response.dragged() {
+                    self.region_start = None;
+                }
+            } else {
+                self.region_start = None;
             }
 
-            // Dragging and Scrolling Logic (disabled when arrow connection is active).
-            if !self.marker_active && !self.eraser_active && !self.arrow_connection_active {
+            // Dragging and Scrolling Logic (only without a drawing or arrow tool).
+            if matches!(self.active_tool, Tool::None | Tool::Measure) && !region_active {
                 if response.drag_started() {
                     self.drag_start = response.interact_pointer_pos().unwrap_or(self.drag_start);
                     self.dragging = true;
+                    self.pan_velocity = egui::Vec2::ZERO;
                 }
                 if response.drag_stopped() {
                     self.dragging = false;
+                    if self.pan_inertia {
+                        self.pan_velocity = ctx.input(|i| i.pointer.velocity());
+                    }
                 }
                 if self.dragging {
                     let current_pos = response.interact_pointer_pos().unwrap();
@@ -862,11 +5514,253 @@ impl App for MyApp {
                 }
             }
 
-            // Zoom Logic.
-            let scroll = ctx.input(|i| i.raw_scroll_delta.y);
-            if scroll != 0.0 {
-                self.zoom *= 1.0 + scroll * 0.001;
-                self.zoom = self.zoom.clamp(0.4, 4.0);
+            // Pan Inertia: keep gliding after a fast drag is released, slowing down each frame.
+            if !self.dragging && self.pan_velocity != egui::Vec2::ZERO {
+                let dt = ctx.input(|i| i.stable_dt).min(0.1);
+                self.offset += self.pan_velocity * dt;
+                self.pan_velocity *= PAN_FRICTION.powf(dt);
+                if self.pan_velocity.length() < 10.0 {
+                    self.pan_velocity = egui::Vec2::ZERO;
+                } else {
+                    ctx.request_repaint();
+                }
+            }
+
+            // Zoom Logic: the canvas point under the pointer stays under the pointer.
+            // Touchpad pinches (and Ctrl+scroll) arrive as `zoom_delta`, and a mouse wheel
+            // zooms. Smooth scrolling pans when it moves sideways or during a touch or pinch,
+            // which tells two-finger touchpad scrolling apart from a smooth-scrolling wheel.
+            // Holding Alt stretches only the horizontal axis, e.g. for timeline layouts.
+            let line_scroll_speed = ctx.options(|o| o.line_scroll_speed);
+            let (wheel, touch_pan, pinch, alt, pointer) = ctx.input(|i| {
+                let touching = i.any_touches() || i.zoom_delta() != 1.0;
+                let mut wheel = 0.0;
+                let mut touch_pan = egui::Vec2::ZERO;
+                for event in &i.events {
+                    let egui::Event::MouseWheel {
+                        unit,
+                        delta,
+                        modifiers,
+                    } = event
+                    else {
+                        continue;
+                    };
+                    if modifiers.command || modifiers.ctrl {
+                        // Already part of `zoom_delta`.
+                        continue;
+                    }
+                    match unit {
+                        egui::MouseWheelUnit::Point
+                            if modifiers.is_none() && (delta.x != 0.0 || touching) =>
+                        {
+                            touch_pan += *delta
+                        }
+                        egui::MouseWheelUnit::Point => wheel += delta.y,
+                        egui::MouseWheelUnit::Line => wheel += delta.y * line_scroll_speed,
+                        egui::MouseWheelUnit::Page => wheel += delta.y * i.screen_rect.height(),
+                    }
+                }
+                (
+                    wheel,
+                    touch_pan,
+                    i.zoom_delta(),
+                    i.modifiers.alt,
+                    i.pointer.hover_pos(),
+                )
+            });
+            // Only over the canvas itself, so scrolling a window's contents doesn't pan it.
+            let over_canvas = pointer.is_some_and(|pos| {
+                ctx.layer_id_at(pos)
+                    .is_some_and(|layer| layer.order == egui::Order::Background)
+            });
+            if over_canvas && touch_pan != egui::Vec2::ZERO {
+                self.offset += touch_pan;
+                self.pan_velocity = egui::Vec2::ZERO;
+            }
+            let factor = (1.0 + wheel * 0.001) * pinch;
+            if factor != 1.0 {
+                let mut zoom = self.zoom;
+                if alt {
+                    zoom.x *= factor;
+                } else {
+                    zoom *= factor;
+                }
+                let zoom = zoom.clamp(egui::Vec2::splat(0.4), egui::Vec2::splat(4.0));
+                let anchor = pointer.unwrap_or(response.rect.center());
+                self.set_zoom_about(anchor, zoom);
+            }
+
+            // Focus/change events of node text fields, used to coalesce text undo entries.
+            let mut text_focus_gained = false;
+            let mut text_changed = false;
+            let mut text_focus_lost = false;
+            // Whether a node's options button took this frame's click.
+            let mut options_clicked = false;
+            // Node whose "Link +" button was clicked; handled once all nodes are drawn.
+            let mut link_from = None;
+            let mut duplicate_from = None;
+            // Node whose "Delete" button was clicked; also handled once all nodes are drawn.
+            let mut node_to_delete = None;
+
+            let edit_request = self.edit_request.take();
+            // Nodes dragged together when any of them is dragged (the multi-node selection).
+            let group = if self.selected_nodes.len() > 1 {
+                self.selected_nodes.clone()
+            } else {
+                Vec::new()
+            };
+            let mut group_drag_started = false;
+            let mut group_drag_stopped = false;
+            let mut group_delta = egui::Vec2::ZERO;
+
+            // Nodes entirely outside the visible canvas are skipped, drawing and interaction
+            // alike, unless their menu is open. Connections still resolve against them.
+            let visible = ui.clip_rect();
+            let menu_node = self.selected_node;
+
+            // Image Nodes Rendering: drawn first, so notes and code nodes go on top.
+            let mut i = 0;
+            while i < self.image_nodes.len() {
+                let node_id = self.image_nodes[i].id;
+                let scaled_size = (self.image_nodes[i].size * self.zoom).max(egui::vec2(1.0, 1.0));
+                let scaled_position = self.to_screen(self.image_nodes[i].position);
+                let rect = egui::Rect::from_min_size(scaled_position, scaled_size);
+                if !visible.intersects(rect) && menu_node != Some((NodeType::Image, node_id)) {
+                    i += 1;
+                    continue;
+                }
+                let texture = self.image_texture(ctx, i);
+                let mut drag_ended = false;
+                let locked = self.image_nodes[i].locked;
+
+                {
+                    // Inner block: mutable borrow of self.image_nodes[i].
+                    let node = &mut self.image_nodes[i];
+                    let id = node_widget_id(NodeType::Image, node.id);
+                    let interact = ui.interact(rect, id, node_sense);
+                    let in_group = group.contains(&(NodeType::Image, node.id));
+                    if in_group {
+                        group_drag_started |= interact.drag_started();
+                        group_drag_stopped |= interact.drag_stopped();
+                        if interact.dragged() {
+                            group_delta += interact.drag_delta() / self.zoom;
+                        }
+                    }
+                    if interact.drag_started() && !in_group && !locked {
+                        node.is_dragging = true;
+                    }
+                    if interact.drag_stopped() && node.is_dragging {
+                        node.is_dragging = false;
+                        drag_ended = true;
+                        if self.snap_to_grid {
+                            node.position = snap_to_grid(node.position, self.grid_spacing);
+                        }
+                    }
+                    if node.is_dragging {
+                        node.position += interact.drag_delta() / self.zoom;
+                    }
+                    let border = egui::Stroke::new(1.0, egui::Color32::from_rgb(80, 80, 80));
+                    if let Some(texture) = &texture {
+                        egui::Image::from_texture(texture).paint_at(ui, rect);
+                    } else {
+                        painter.rect_filled(rect, 0.0, NOTE_BACKGROUND);
+                        painter.text(
+                            rect.center(),
+                            egui::Align2::CENTER_CENTER,
+                            node.source.description(),
+                            egui::FontId::monospace(6.0 * self.zoom.min_elem()),
+                            egui::Color32::from_rgb(187, 192, 206),
+                        );
+                    }
+                    painter.rect_stroke(rect, 0.0, border, egui::StrokeKind::Inside);
+                    // Options button at the top right.
+                    ui.allocate_new_ui(egui::UiBuilder::new().max_rect(rect), |ui| {
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+                            if ui.button("o").on_hover_text("Options").clicked() {
+                                options_clicked = true;
+                                let key = (NodeType::Image, node.id);
+                                if self.selected_node == Some(key) {
+                                    self.selected_node = None;
+                                } else {
+                                    self.selected_node = Some(key);
+                                }
+                            }
+                        });
+                    });
+                } // End inner block: mutable borrow of self.image_nodes[i] is dropped.
+                if !locked {
+                    self.node_resize_handle(ui, NodeType::Image, node_id, rect);
+                }
+
+                if drag_ended {
+                    self.record_state();
+                }
+                // Render floating menu using local copies.
+                if self.selected_node == Some((NodeType::Image, node_id)) {
+                    let screen_rect = ctx.screen_rect();
+                    let menu_pos = floating_menu_pos(rect, screen_rect);
+                    egui::Area::new(format!("image_menu_{}", node_id).into())
+                        .fixed_pos(menu_pos)
+                        .constrain_to(screen_rect)
+                        .show(ctx, |ui| {
+                            let mut to_remove = false;
+                            ui.horizontal(|ui| {
+                                if ui.button("To Back").clicked() && i > 0 {
+                                    self.record_state();
+                                    let node = self.image_nodes.remove(i);
+                                    self.image_nodes.insert(0, node);
+                                }
+                                if ui.button("Backward").clicked() && i > 0 {
+                                    self.record_state();
+                                    self.image_nodes.swap(i, i - 1);
+                                }
+                                if ui.button("Forward").clicked() && i < self.image_nodes.len() - 1 {
+                                    self.record_state();
+                                    self.image_nodes.swap(i, i + 1);
+                                }
+                                let last = self.image_nodes.len() - 1;
+                                if ui.button("To Front").clicked() && i < last {
+                                    self.record_state();
+                                    let node = self.image_nodes.remove(i);
+                                    self.image_nodes.push(node);
+                                }
+                                // The picture's own aspect ratio at the default width.
+                                if let Some(texture) = &texture {
+                                    if ui.button("Reset Size").clicked() {
+                                        self.record_state();
+                                        let [width, height] = texture.size();
+                                        self.image_nodes[i].size = egui::vec2(
+                                            DEFAULT_IMAGE_WIDTH,
+                                            DEFAULT_IMAGE_WIDTH * height as f32 / width.max(1) as f32,
+                                        )
+                                        .clamp(egui::Vec2::splat(1.0), egui::Vec2::splat(400.0));
+                                    }
+                                }
+                                let lock_label = if locked { "Unlock" } else { "Lock" };
+                                if ui.button(lock_label).clicked() {
+                                    self.record_state();
+                                    self.image_nodes[i].locked = !locked;
+                                }
+                                if ui
+                                    .button("Link +")
+                                    .on_hover_text("Add a connected note next to this one")
+                                    .clicked()
+                                {
+                                    link_from = Some((NodeType::Image, node_id));
+                                }
+                                if ui.button("Duplicate").clicked() {
+                                    duplicate_from = Some((NodeType::Image, node_id));
+                                }
+                                if ui.button("Delete").clicked() {
+                                    to_remove = true;
+                                }
+                            });
+                            if to_remove {
+                                node_to_delete = Some((NodeType::Image, node_id));
+                            }
+                        });
+                }
+                i += 1;
             }
 
             // Note Nodes Rendering.
@@ -875,8 +5769,12 @@ impl App for MyApp {
                 // Extract local copies before mutable borrow.
                 let note_id = self.note_nodes[i].id;
                 let scaled_size = (self.note_nodes[i].size * self.zoom).max(egui::vec2(1.0, 1.0));
-                let scaled_position = (self.note_nodes[i].position * self.zoom) + self.offset;
+                let scaled_position = self.to_screen(self.note_nodes[i].position);
                 let rect = egui::Rect::from_min_size(scaled_position, scaled_size);
+                if !visible.intersects(rect) && menu_node != Some((NodeType::Note, note_id)) {
+                    i += 1;
+                    continue;
+                }
 
                 // Local flags to track state changes.
                 let mut lock_changed = false;
@@ -885,57 +5783,104 @@ impl App for MyApp {
                 {
                     // Inner block: mutable borrow of self.note_nodes[i].
                     let note = &mut self.note_nodes[i];
-                    let id = ui.make_persistent_id(note.id);
-                    let interact = ui.interact(rect, id, egui::Sense::click_and_drag());
-                    if interact.drag_started() {
+                    let id = node_widget_id(NodeType::Note, note.id);
+                    let interact = ui.interact(rect, id, node_sense);
+                    let in_group = group.contains(&(NodeType::Note, note.id));
+                    if in_group {
+                        group_drag_started |= interact.drag_started();
+                        group_drag_stopped |= interact.drag_stopped();
+                        if interact.dragged() {
+                            group_delta += interact.drag_delta() / self.zoom;
+                        }
+                    }
+                    if interact.drag_started() && !in_group {
                         note.is_dragging = true;
                     }
-                    if interact.drag_stopped() {
+                    if interact.drag_stopped() && !in_group {
                         note.is_dragging = false;
                         drag_ended = true;
+                        if self.snap_to_grid {
+                            note.position = snap_to_grid(note.position, self.grid_spacing);
+                        }
                     }
                     if note.is_dragging {
                         note.position += interact.drag_delta() / self.zoom;
                     }
+                    let background = note.bg_color;
+                    let text_color = note.text_color;
                     ui.allocate_ui_at_rect(rect, |ui| {
                         egui::Frame::NONE
-                            .fill(egui::Color32::from_rgb(32, 37, 43))
+                            .fill(background)
                             .stroke(egui::Stroke::new(1.0, egui::Color32::from_rgb(80, 80, 80)))
                             .show(ui, |ui| {
-                                let font_id = egui::FontId::monospace(6.0 * self.zoom);
+                                let font_id = egui::FontId::monospace(6.0 * self.zoom.min_elem());
                                 // Options button at the top right.
                                 ui.with_layout(
                                     egui::Layout::right_to_left(egui::Align::TOP),
                                     |ui| {
                                         if ui.button("o").on_hover_text("Options").clicked() {
-                                            if self.selected_node == Some(i) {
+                                            options_clicked = true;
+                                            let key = (NodeType::Note, note.id);
+                                            if self.selected_node == Some(key) {
                                                 self.selected_node = None;
                                             } else {
-                                                self.selected_node = Some(i);
+                                                self.selected_node = Some(key);
                                             }
                                         }
                                     },
                                 );
-                                if note.locked {
-                                    ui.add(
+                                if note.locked && note.render_markdown {
+                                    let mut job = markdown_job(
+                                        &note.text,
+                                        6.0 * self.zoom.min_elem(),
+                                        text_color,
+                                    );
+                                    job.wrap.max_width = scaled_size.x;
+                                    let (text_rect, _) =
+                                        ui.allocate_exact_size(scaled_size, egui::Sense::hover());
+                                    ui.put(text_rect, |ui: &mut egui::Ui| {
+                                        egui::ScrollArea::vertical()
+                                            .id_salt(("note_text", note.id))
+                                            .auto_shrink(false)
+                                            .show(ui, |ui| ui.label(job))
+                                            .inner
+                                    });
+                                } else if note.locked {
+                                    scrolled_text_edit(
+                                        ui,
+                                        ("note_text", note.id),
+                                        scaled_size,
                                         egui::TextEdit::multiline(&mut note.text)
                                             .font(font_id.clone())
                                             .frame(false)
                                             .interactive(false)
-                                            .text_color(egui::Color32::from_rgb(187, 192, 206)),
+                                            .text_color(text_color),
                                     );
                                 } else {
                                     ui.vertical(|ui| {
-                                        ui.add_sized(
+                                        let text_response = scrolled_text_edit(
+                                            ui,
+                                            ("note_text", note.id),
                                             scaled_size,
                                             egui::TextEdit::multiline(&mut note.text)
                                                 .font(font_id.clone())
                                                 .frame(false)
-                                                .background_color(egui::Color32::from_rgb(
-                                                    32, 37, 43,
-                                                ))
-                                                .text_color(egui::Color32::from_rgb(187, 192, 206)),
+                                                .background_color(background)
+                                                .text_color(text_color),
                                         );
+                                        if edit_request == Some((NodeType::Note, note.id)) {
+                                            text_response.request_focus();
+                                        }
+                                        text_focus_gained |= text_response.gained_focus();
+                                        text_changed |= text_response.changed();
+                                        text_focus_lost |= text_response.lost_focus();
+                                        // Escape leaves the field and locks the note.
+                                        if text_response.lost_focus()
+                                            && ui.input(|i| i.key_pressed(egui::Key::Escape))
+                                        {
+                                            note.locked = true;
+                                            lock_changed = true;
+                                        }
                                         ui.with_layout(
                                             egui::Layout::right_to_left(egui::Align::Center),
                                             |ui| {
@@ -956,37 +5901,113 @@ impl App for MyApp {
                             });
                     });
                 } // End inner block: mutable borrow of self.note_nodes[i] is dropped.
+                self.node_resize_handle(ui, NodeType::Note, note_id, rect);
 
                 // If a drag ended or the node was locked, record state.
                 if drag_ended || lock_changed {
                     self.record_state();
                 }
                 // Render floating menu using local copies.
-                if Some(i) == self.selected_node {
-                    let menu_pos = scaled_position + egui::vec2(0.0, -25.0);
+                if self.selected_node == Some((NodeType::Note, note_id)) {
+                    let screen_rect = ctx.screen_rect();
+                    let menu_pos = floating_menu_pos(rect, screen_rect);
                     egui::Area::new(format!("note_menu_{}", note_id).into())
                         .fixed_pos(menu_pos)
+                        .constrain_to(screen_rect)
                         .show(ctx, |ui| {
                             let mut to_remove = false;
                             ui.horizontal(|ui| {
+                                if ui.button("To Back").clicked() && i > 0 {
+                                    self.record_state();
+                                    let note = self.note_nodes.remove(i);
+                                    self.note_nodes.insert(0, note);
+                                }
                                 if ui.button("Backward").clicked() && i > 0 {
                                     self.record_state();
                                     self.note_nodes.swap(i, i - 1);
-                                    self.selected_node = Some(i - 1);
                                 }
                                 if ui.button("Forward").clicked() && i < self.note_nodes.len() - 1 {
                                     self.record_state();
                                     self.note_nodes.swap(i, i + 1);
-                                    self.selected_node = Some(i + 1);
+                                }
+                                let last = self.note_nodes.len() - 1;
+                                if ui.button("To Front").clicked() && i < last {
+                                    self.record_state();
+                                    let note = self.note_nodes.remove(i);
+                                    self.note_nodes.push(note);
+                                }
+                                if ui.button("Reset Size").clicked() {
+                                    self.record_state();
+                                    self.note_nodes[i].size = DEFAULT_NOTE_SIZE;
+                                }
+                                let mut color = self.node_background(NodeType::Note, note_id);
+                                if egui::color_picker::color_edit_button_srgba(
+                                    ui,
+                                    &mut color,
+                                    egui::color_picker::Alpha::Opaque,
+                                )
+                                .on_hover_text("Background color")
+                                .changed()
+                                {
+                                    self.record_state_coalesced();
+                                    self.note_nodes[i].bg_color = color;
+                                }
+                                let mut color =
+                                    self.note_nodes[i].text_color;
+                                if egui::color_picker::color_edit_button_srgba(
+                                    ui,
+                                    &mut color,
+                                    egui::color_picker::Alpha::Opaque,
+                                )
+                                .on_hover_text("Text color")
+                                .changed()
+                                {
+                                    self.record_state_coalesced();
+                                    self.note_nodes[i].text_color = color;
+                                }
+                                let mut render = self.note_nodes[i].render_markdown;
+                                if ui
+                                    .checkbox(&mut render, "Render")
+                                    .on_hover_text("Show the note as markdown while it is locked")
+                                    .changed()
+                                {
+                                    self.record_state();
+                                    self.note_nodes[i].render_markdown = render;
+                                }
+                                if self.note_nodes[i].locked && ui.button("Unlock").clicked() {
+                                    self.record_state();
+                                    self.note_nodes[i].locked = false;
+                                }
+                                ui.menu_button("Tags", |ui| {
+                                    self.tags_menu(ui, NodeType::Note, note_id);
+                                });
+                                if ui
+                                    .button("Link +")
+                                    .on_hover_text("Add a connected note next to this one")
+                                    .clicked()
+                                {
+                                    link_from = Some((NodeType::Note, note_id));
+                                }
+                                if ui.button("Duplicate").clicked() {
+                                    duplicate_from = Some((NodeType::Note, note_id));
+                                }
+                                if ui.button("Export").clicked() {
+                                    if let Some(path) = rfd::FileDialog::new()
+                                        .add_filter("Text", &["txt", "md"])
+                                        .set_file_name("note.txt")
+                                        .save_file()
+                                    {
+                                        if let Err(e) = fs::write(&path, &self.note_nodes[i].text) {
+                                            self.log_error(format!("Note export error: {}", e));
+                                        }
+                                    }
                                 }
                                 if ui.button("Delete").clicked() {
                                     to_remove = true;
                                 }
                             });
                             if to_remove {
-                                self.record_state();
-                                self.note_nodes.remove(i);
-                                self.selected_node = None;
+                                node_to_delete = Some((NodeType::Note, note_id));
                             }
                         });
                 }
@@ -998,47 +6019,68 @@ impl App for MyApp {
                 // Extract local copies before mutable borrow.
                 let node_id = self.code_nodes[i].id;
                 let scaled_size = (self.code_nodes[i].size * self.zoom).max(egui::vec2(1.0, 1.0));
-                let scaled_position = (self.code_nodes[i].position * self.zoom) + self.offset;
+                let scaled_position = self.to_screen(self.code_nodes[i].position);
                 let rect = egui::Rect::from_min_size(scaled_position, scaled_size);
+                if !visible.intersects(rect) && menu_node != Some((NodeType::Code, node_id)) {
+                    continue;
+                }
                 // Flags to track changes.
                 let mut lock_changed = false;
                 let mut drag_ended = false;
+                let mut theme_changed = false;
+                let mut new_language = None;
 
                 {
                     // Inner block: mutable borrow of self.code_nodes[i].
                     let node = &mut self.code_nodes[i];
-                    let id = ui.make_persistent_id(node.id + 10_000);
-                    let interact = ui.interact(rect, id, egui::Sense::click_and_drag());
-                    if interact.drag_started() {
+                    let id = node_widget_id(NodeType::Code, node.id);
+                    let interact = ui.interact(rect, id, node_sense);
+                    let in_group = group.contains(&(NodeType::Code, node.id));
+                    if in_group {
+                        group_drag_started |= interact.drag_started();
+                        group_drag_stopped |= interact.drag_stopped();
+                        if interact.dragged() {
+                            group_delta += interact.drag_delta() / self.zoom;
+                        }
+                    }
+                    if interact.drag_started() && !in_group {
                         node.is_dragging = true;
                     }
-                    if interact.drag_stopped() {
+                    if interact.drag_stopped() && !in_group {
                         node.is_dragging = false;
                         drag_ended = true;
+                        if self.snap_to_grid {
+                            node.position = snap_to_grid(node.position, self.grid_spacing);
+                        }
                     }
                     if node.is_dragging {
                         node.position += interact.drag_delta() / self.zoom;
                     }
+                    let theme = code_theme(&node.theme);
+                    // A synced node whose snippet is gone from its file gets a red border.
+                    let border = if node.sync && node.sync_lost {
+                        egui::Stroke::new(2.0, ui.visuals().error_fg_color)
+                    } else {
+                        egui::Stroke::new(1.0, egui::Color32::from_rgb(100, 100, 100))
+                    };
                     ui.allocate_ui_at_rect(rect, |ui| {
                         egui::Frame::NONE
-                            .fill(egui::Color32::from_rgb(30, 35, 40))
-                            .stroke(egui::Stroke::new(
-                                1.0,
-                                egui::Color32::from_rgb(100, 100, 100),
-                            ))
+                            .fill(node.bg_color.unwrap_or(theme.background))
+                            .stroke(border)
                             .show(ui, |ui| {
-                                let font_id = egui::FontId::monospace(5.0 * self.zoom);
-                                let row_count = (scaled_size.y / (5.0 * self.zoom)).ceil() as usize;
+                                let font_id = egui::FontId::monospace(5.0 * self.zoom.min_elem());
+                                let row_count = (scaled_size.y / (5.0 * self.zoom.min_elem())).ceil() as usize;
                                 // Options button at top right.
                                 ui.with_layout(
                                     egui::Layout::right_to_left(egui::Align::TOP),
                                     |ui| {
                                         if ui.button("o").on_hover_text("Options").clicked() {
-                                            let code_index = i + self.note_nodes.len();
-                                            if self.selected_node == Some(code_index) {
+                                            options_clicked = true;
+                                            let key = (NodeType::Code, node.id);
+                                            if self.selected_node == Some(key) {
                                                 self.selected_node = None;
                                             } else {
-                                                self.selected_node = Some(code_index);
+                                                self.selected_node = Some(key);
                                             }
                                         }
                                     },
@@ -1052,7 +6094,11 @@ impl App for MyApp {
                                                 egui::RichText::new(&node.file_path)
                                                     .font(font_id.clone())
                                                     .color(egui::Color32::BLACK),
-                                            );
+                                            )
+                                            .on_hover_text(code_location_text(
+                                                node,
+                                                self.project_root.as_deref(),
+                                            ));
                                         });
                                     let offset_val = node.line_offset.unwrap_or(1);
                                     let display_code = node
@@ -1062,13 +6108,31 @@ impl App for MyApp {
                                         .map(|(i, line)| format!("{:>4}: {}", i + offset_val, line))
                                         .collect::<Vec<_>>()
                                         .join("\n");
-                                    ui.add_sized(
+                                    let syntax_name = code_syntax_name(node).unwrap_or("");
+                                    // Highlighted layout of the same text as `display_code`.
+                                    let mut layouter = |ui: &egui::Ui, _: &str, wrap_width: f32| {
+                                        let mut job = ui.ctx().memory_mut(|mem| {
+                                            mem.caches.cache::<HighlightCache>().get((
+                                                node.code.as_str(),
+                                                syntax_name,
+                                                offset_val,
+                                                theme.name,
+                                                &font_id,
+                                            ))
+                                        });
+                                        job.wrap.max_width = wrap_width;
+                                        ui.fonts(|fonts| fonts.layout_job(job))
+                                    };
+                                    scrolled_text_edit(
+                                        ui,
+                                        ("code_text", node.id),
                                         scaled_size,
                                         egui::TextEdit::multiline(&mut display_code.clone())
                                             .font(font_id.clone())
                                             .frame(false)
                                             .desired_rows(row_count)
-                                            .text_color(egui::Color32::from_rgb(187, 192, 206))
+                                            .text_color(theme.text)
+                                            .layouter(&mut layouter)
                                             .interactive(false),
                                     );
                                 } else {
@@ -1085,23 +6149,39 @@ impl App for MyApp {
                                                     .color(egui::Color32::BLACK),
                                                 );
                                             });
-                                        ui.add(
-                                            egui::TextEdit::singleline(&mut node.file_path)
-                                                .font(font_id.clone()),
-                                        );
-                                        // Reserve an exact area for the code text edit.
-                                        let (text_edit_rect, _resp) = ui
-                                            .allocate_exact_size(scaled_size, egui::Sense::hover());
-                                        ui.put(text_edit_rect, |ui: &mut egui::Ui| {
-                                            ui.add(
-                                                egui::TextEdit::multiline(&mut node.code)
-                                                    .font(font_id.clone())
-                                                    .frame(false)
-                                                    .text_color(egui::Color32::from_rgb(
-                                                        187, 192, 206,
-                                                    )),
+                                        let location =
+                                            code_location_text(node, self.project_root.as_deref());
+                                        let path_response = ui
+                                            .add(
+                                                egui::TextEdit::singleline(&mut node.file_path)
+                                                    .font(font_id.clone()),
                                             )
-                                        });
+                                            .on_hover_text(location);
+                                        let code_response = scrolled_text_edit(
+                                            ui,
+                                            ("code_text", node.id),
+                                            scaled_size,
+                                            egui::TextEdit::multiline(&mut node.code)
+                                                .font(font_id.clone())
+                                                .frame(false)
+                                                .text_color(theme.text),
+                                        );
+                                        if edit_request == Some((NodeType::Code, node.id)) {
+                                            code_response.request_focus();
+                                        }
+                                        for text_response in [&path_response, &code_response] {
+                                            text_focus_gained |= text_response.gained_focus();
+                                            text_changed |= text_response.changed();
+                                            text_focus_lost |= text_response.lost_focus();
+                                        }
+                                        // Escape leaves the field and locks the node.
+                                        let escaped = (path_response.lost_focus()
+                                            || code_response.lost_focus())
+                                            && ui.input(|i| i.key_pressed(egui::Key::Escape));
+                                        if escaped {
+                                            lock_code_node(node, self.project_root.as_deref());
+                                            lock_changed = true;
+                                        }
                                         // Lock button at the bottom right.
                                         ui.with_layout(
                                             egui::Layout::right_to_left(egui::Align::Center),
@@ -1111,32 +6191,48 @@ impl App for MyApp {
                                                     .on_hover_text("Lock Code Node")
                                                     .clicked()
                                                 {
-                                                    node.locked = true;
-                                                    if let Some(project_root) = &self.project_root {
-                                                        let full_path =
-                                                            project_root.join(&node.file_path);
-                                                        if let Ok(contents) =
-                                                            fs::read_to_string(&full_path)
-                                                        {
-                                                            let snippet_raw =
-                                                                node.code.replace("\r\n", "\n");
-                                                            let snippet = snippet_raw.trim_end();
-                                                            let file =
-                                                                contents.replace("\r\n", "\n");
-
-                                                            node.line_offset = file
-                                                                .lines()
-                                                                .collect::<Vec<_>>()
-                                                                .windows(snippet.lines().count())
-                                                                .position(|window| {
-                                                                    window.join("\n").trim_end()
-                                                                        == snippet
-                                                                })
-                                                                .map(|i| i + 1);
-                                                        }
-                                                    }
+                                                    lock_code_node(
+                                                        node,
+                                                        self.project_root.as_deref(),
+                                                    );
                                                     lock_changed = true;
                                                 }
+                                                egui::ComboBox::from_id_salt(("code_theme", node.id))
+                                                    .selected_text(theme.name)
+                                                    .show_ui(ui, |ui| {
+                                                        for option in &CODE_THEMES {
+                                                            if ui
+                                                                .selectable_label(
+                                                                    theme.name == option.name,
+                                                                    option.name,
+                                                                )
+                                                                .clicked()
+                                                                && theme.name != option.name
+                                                            {
+                                                                node.theme = option.name.to_string();
+                                                                theme_changed = true;
+                                                            }
+                                                        }
+                                                    });
+                                                // "Auto" follows the file extension.
+                                                let language = node.language.as_deref();
+                                                egui::ComboBox::from_id_salt(("language", node.id))
+                                                    .selected_text(language.unwrap_or("Auto"))
+                                                    .show_ui(ui, |ui| {
+                                                        for option in language_options() {
+                                                            let selected = language == option;
+                                                            if ui
+                                                                .selectable_label(
+                                                                    selected,
+                                                                    option.unwrap_or("Auto"),
+                                                                )
+                                                                .clicked()
+                                                                && !selected
+                                                            {
+                                                                new_language = Some(option);
+                                                            }
+                                                        }
+                                                    });
                                             },
                                         );
                                     });
@@ -1146,51 +6242,508 @@ impl App for MyApp {
                             });
                     });
                 } // End inner block; mutable borrow of self.code_nodes[i] is dropped.
+                self.node_resize_handle(ui, NodeType::Code, node_id, rect);
+
+                if let Some(language) = new_language {
+                    self.code_nodes[i].language = language.map(str::to_string);
+                }
+                // If dragging ended, the node was locked or its theme or language changed, record
+                // state.
+                if drag_ended || lock_changed || theme_changed || new_language.is_some() {
+                    self.record_state();
+                }
+                if lock_changed
+                    && self.project_root.is_some()
+                    && self.code_nodes[i].line_offset.is_none()
+                {
+                    let file_path = self.code_nodes[i].file_path.clone();
+                    self.log_error(format!("Code node snippet not found in {}", file_path));
+                }
+                // Render floating menu using the local copy of the scaled position.
+                if self.selected_node == Some((NodeType::Code, node_id)) {
+                    let screen_rect = ctx.screen_rect();
+                    let menu_pos = floating_menu_pos(rect, screen_rect);
+                    egui::Area::new(format!("code_menu_{}", node_id).into())
+                        .fixed_pos(menu_pos)
+                        .constrain_to(screen_rect)
+                        .show(ctx, |ui| {
+                            let mut to_remove = false;
+                            ui.horizontal(|ui| {
+                                if ui.button("To Back").clicked() && i > 0 {
+                                    self.record_state();
+                                    let node = self.code_nodes.remove(i);
+                                    self.code_nodes.insert(0, node);
+                                }
+                                if ui.button("Backward").clicked() && i > 0 {
+                                    self.record_state();
+                                    self.code_nodes.swap(i, i - 1);
+                                }
+                                if ui.button("Forward").clicked() && i < self.code_nodes.len() - 1 {
+                                    self.record_state();
+                                    self.code_nodes.swap(i, i + 1);
+                                }
+                                let last = self.code_nodes.len() - 1;
+                                if ui.button("To Front").clicked() && i < last {
+                                    self.record_state();
+                                    let node = self.code_nodes.remove(i);
+                                    self.code_nodes.push(node);
+                                }
+                                if ui.button("Reset Size").clicked() {
+                                    self.record_state();
+                                    self.code_nodes[i].size = DEFAULT_CODE_SIZE;
+                                }
+                                let mut color = self.node_background(NodeType::Code, node_id);
+                                if egui::color_picker::color_edit_button_srgba(
+                                    ui,
+                                    &mut color,
+                                    egui::color_picker::Alpha::Opaque,
+                                )
+                                .on_hover_text("Background color")
+                                .changed()
+                                {
+                                    self.record_state_coalesced();
+                                    self.code_nodes[i].bg_color = Some(color);
+                                }
+                                let mut sync = self.code_nodes[i].sync;
+                                if ui
+                                    .checkbox(&mut sync, "Sync")
+                                    .on_hover_text("Follow the snippet when its file changes on disk")
+                                    .changed()
+                                {
+                                    self.record_state();
+                                    let node = &mut self.code_nodes[i];
+                                    node.sync = sync;
+                                    node.synced_modified = None;
+                                    node.sync_lost = false;
+                                }
+                                ui.menu_button("Tags", |ui| {
+                                    self.tags_menu(ui, NodeType::Code, node_id);
+                                });
+                                if ui
+                                    .button("Link +")
+                                    .on_hover_text("Add a connected note next to this one")
+                                    .clicked()
+                                {
+                                    link_from = Some((NodeType::Code, node_id));
+                                }
+                                if ui.button("Duplicate").clicked() {
+                                    duplicate_from = Some((NodeType::Code, node_id));
+                                }
+                                if ui.button("Export").clicked() {
+                                    // Suggest the basename of the node's file path, if it has one.
+                                    let file_name = std::path::Path::new(&self.code_nodes[i].file_path)
+                                        .file_name()
+                                        .and_then(|name| name.to_str())
+                                        .unwrap_or("snippet.txt")
+                                        .to_string();
+                                    if let Some(path) =
+                                        rfd::FileDialog::new().set_file_name(file_name).save_file()
+                                    {
+                                        if let Err(e) = fs::write(&path, &self.code_nodes[i].code) {
+                                            self.log_error(format!("Code export error: {}", e));
+                                        }
+                                    }
+                                }
+                                if ui.button("Delete").clicked() {
+                                    to_remove = true;
+                                }
+                            });
+                            if to_remove {
+                                node_to_delete = Some((NodeType::Code, node_id));
+                            }
+                        });
+                }
+            }
+
+            self.track_text_edits(ctx, text_focus_gained, text_changed, text_focus_lost);
+
+            if let Some((node_type, id)) = node_to_delete {
+                self.delete_node(node_type, id);
+            }
+
+            // Group Move: one undo entry, recorded before the selected nodes start moving.
+            if group_drag_started {
+                self.record_state();
+            }
+            if group_delta != egui::Vec2::ZERO || group_drag_stopped {
+                let snap = group_drag_stopped && self.snap_to_grid;
+                let spacing = self.grid_spacing;
+                for (node_type, id) in &group {
+                    if let Some(position) = self.node_position_mut(*node_type, *id) {
+                        *position += group_delta;
+                        if snap {
+                            *position = snap_to_grid(*position, spacing);
+                        }
+                    }
+                }
+            }
+
+            // Select the new linked note so the map can keep growing from it.
+            if let Some(key) = duplicate_from {
+                self.duplicate_and_select(&[key]);
+            }
+            if let Some((from_type, from_id)) = link_from {
+                if let Some(id) = self.add_linked_note(from_type, from_id) {
+                    self.selected_nodes = vec![(NodeType::Note, id)];
+                    self.selected_node = Some((NodeType::Note, id));
+                }
+            }
+
+            // A click on the canvas, over a node or not. Clicks on floating menus and the tools
+            // bar are ignored.
+            let canvas_click = ctx
+                .input(|i| {
+                    i.pointer
+                        .primary_clicked()
+                        .then(|| i.pointer.interact_pos())
+                        .flatten()
+                })
+                .filter(|pos| {
+                    response.rect.contains(*pos)
+                        && ctx
+                            .layer_id_at(*pos)
+                            .is_none_or(|layer| layer.order == egui::Order::Background)
+                });
+
+            // Select Tool: a click selects the topmost entity under the cursor.
+            if self.active_tool == Tool::Select && !options_clicked {
+                if let Some(pos) = canvas_click {
+                    // Shift adds to (or removes from) the selection instead of replacing it.
+                    let shift = ctx.input(|i| i.modifiers.shift);
+                    if !shift {
+                        self.selected_nodes.clear();
+                        self.selected_connections.clear();
+                        self.selected_strokes.clear();
+                    }
+                    match self.hit_test(pos, &curves) {
+                        Some(CanvasHit::Node(node_type, id)) => {
+                            let key = (node_type, id);
+                            if let Some(index) = self.selected_nodes.iter().position(|k| *k == key)
+                            {
+                                self.selected_nodes.remove(index);
+                            } else {
+                                self.selected_nodes.push(key);
+                            }
+                        }
+                        Some(CanvasHit::Connection(id)) => {
+                            let newly_selected = self.selected_connections.insert(id);
+                            if !newly_selected {
+                                self.selected_connections.remove(&id);
+                            }
+                        }
+                        Some(CanvasHit::Stroke(index)) => {
+                            if let Some(position) =
+                                self.selected_strokes.iter().position(|i| *i == index)
+                            {
+                                self.selected_strokes.remove(position);
+                            } else {
+                                self.selected_strokes.push(index);
+                            }
+                        }
+                        None => {}
+                    }
+                    // The options menu only belongs to a single selected node.
+                    self.selected_node = match self.selected_nodes.as_slice() {
+                        &[key] => Some(key),
+                        _ => None,
+                    };
+                    // Menus for the new selection were laid out earlier in this frame.
+                    ctx.request_repaint();
+                }
+            }
+
+            // Double-Click: see `handle_double_click` for the actions and their priority.
+            let canvas_double_click = ctx
+                .input(|i| {
+                    i.pointer
+                        .button_double_clicked(egui::PointerButton::Primary)
+                        .then(|| i.pointer.interact_pos())
+                        .flatten()
+                })
+                .filter(|pos| {
+                    response.rect.contains(*pos)
+                        && ctx
+                            .layer_id_at(*pos)
+                            .is_none_or(|layer| layer.order == egui::Order::Background)
+                });
+            if matches!(self.active_tool, Tool::None | Tool::Select) && !options_clicked {
+                if let Some(pos) = canvas_double_click {
+                    self.handle_double_click(pos, &curves);
+                    ctx.request_repaint();
+                }
+            }
+
+            // Measure Tool: the first click sets the start point, the second adds the measurement.
+            if self.active_tool == Tool::Measure {
+                if let Some(pos) = canvas_click {
+                    let canvas_pos = self.to_canvas(pos);
+                    if let Some(start) = self.measure_start.take() {
+                        self.record_state();
+                        self.measurements.push(Measurement {
+                            start,
+                            end: canvas_pos,
+                        });
+                    } else {
+                        self.measure_start = Some(canvas_pos);
+                    }
+                }
+            }
+
+            // Tags: chips above tagged nodes, and with a tag filter set, dim the nodes that
+            // have none of its tags.
+            let all_tags = self.all_tags();
+            self.tag_filter.retain(|tag| all_tags.contains(tag));
+            let all_nodes: Vec<(NodeType, usize)> = self
+                .note_nodes
+                .iter()
+                .map(|n| (NodeType::Note, n.id))
+                .chain(self.code_nodes.iter().map(|n| (NodeType::Code, n.id)))
+                .chain(self.image_nodes.iter().map(|n| (NodeType::Image, n.id)))
+                .collect();
+            for (node_type, id) in all_nodes {
+                let Some(r) = self.node_rect(node_type, id) else {
+                    continue;
+                };
+                let rect = egui::Rect::from_min_size(self.to_screen(r.min), r.size() * self.zoom);
+                if !visible.intersects(rect.expand(10.0 * self.zoom.y)) {
+                    continue;
+                }
+                let tags = self.node_tags(node_type, id);
+                paint_tag_chips(&painter, rect, tags, self.zoom.min_elem());
+                let shown = self.tag_filter.is_empty()
+                    || tags.iter().any(|tag| self.tag_filter.contains(tag));
+                if !shown {
+                    painter.rect_filled(rect, 0.0, ui.visuals().panel_fill.gamma_multiply(0.8));
+                }
+            }
 
-                // If dragging ended or the node was locked, record state.
-                if drag_ended || lock_changed {
-                    self.record_state();
+            // Orphan Highlighting: outline nodes that no connection touches.
+            if self.highlight_orphans {
+                for (node_type, id) in self.orphan_nodes() {
+                    if let Some(r) = self.node_rect(node_type, id) {
+                        let rect =
+                            egui::Rect::from_min_size(self.to_screen(r.min), r.size() * self.zoom);
+                        painter.rect_stroke(
+                            rect.expand(4.0),
+                            2.0,
+                            egui::Stroke::new(1.5, ORPHAN_COLOR),
+                            egui::StrokeKind::Outside,
+                        );
+                    }
                 }
-                // Render floating menu using the local copy of the scaled position.
-                if Some(i + self.note_nodes.len()) == self.selected_node {
-                    let menu_pos = scaled_position + egui::vec2(0.0, -25.0);
-                    egui::Area::new(format!("code_menu_{}", node_id).into())
-                        .fixed_pos(menu_pos)
-                        .show(ctx, |ui| {
-                            let mut to_remove = false;
-                            ui.horizontal(|ui| {
-                                if ui.button("Backward").clicked() && i > 0 {
-                                    self.record_state();
-                                    self.code_nodes.swap(i, i - 1);
-                                    self.selected_node = Some(i - 1 + self.note_nodes.len());
-                                }
-                                if ui.button("Forward").clicked() && i < self.code_nodes.len() - 1 {
-                                    self.record_state();
-                                    self.code_nodes.swap(i, i + 1);
-                                    self.selected_node = Some(i + 1 + self.note_nodes.len());
-                                }
-                                if ui.button("Delete").clicked() {
-                                    to_remove = true;
-                                }
-                            });
-                            if to_remove {
-                                self.record_state();
-                                self.code_nodes.remove(i);
-                                self.selected_node = None;
+            }
+
+            // Search Highlighting: outline nodes matching the search text.
+            for (node_type, id, _) in self.search_nodes(&self.search_query) {
+                if let Some(r) = self.node_rect(node_type, id) {
+                    let rect = egui::Rect::from_min_size(self.to_screen(r.min), r.size() * self.zoom);
+                    painter.rect_stroke(
+                        rect.expand(3.0),
+                        2.0,
+                        egui::Stroke::new(1.5, SEARCH_COLOR),
+                        egui::StrokeKind::Outside,
+                    );
+                }
+            }
+
+            // Multi-node Selection: outline each selected node and offer group actions.
+            self.selected_nodes = std::mem::take(&mut self.selected_nodes)
+                .into_iter()
+                .filter(|(node_type, id)| self.node_rect(*node_type, *id).is_some())
+                .collect();
+            let selected_rects: Vec<egui::Rect> = self
+                .selected_nodes
+                .iter()
+                .filter_map(|(node_type, id)| self.node_rect(*node_type, *id))
+                .map(|r| egui::Rect::from_min_size(self.to_screen(r.min), r.size() * self.zoom))
+                .collect();
+            for rect in &selected_rects {
+                painter.rect_stroke(
+                    rect.expand(2.0),
+                    2.0,
+                    egui::Stroke::new(2.0, SELECTION_COLOR),
+                    egui::StrokeKind::Outside,
+                );
+            }
+            if selected_rects.len() > 1 {
+                let bounds = selected_rects
+                    .iter()
+                    .fold(egui::Rect::NOTHING, |acc, r| acc.union(*r));
+                let screen_rect = ctx.screen_rect();
+                let mut duplicate = false;
+                let mut recolor = None;
+                egui::Area::new("selection_menu".into())
+                    .fixed_pos(floating_menu_pos(bounds, screen_rect))
+                    .constrain_to(screen_rect)
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{} nodes", selected_rects.len()));
+                            let (node_type, id) = self.selected_nodes[0];
+                            let mut color = self.node_background(node_type, id);
+                            if egui::color_picker::color_edit_button_srgba(
+                                ui,
+                                &mut color,
+                                egui::color_picker::Alpha::Opaque,
+                            )
+                            .on_hover_text("Background color of every selected node")
+                            .changed()
+                            {
+                                recolor = Some(color);
+                            }
+                            if ui
+                                .button("Duplicate")
+                                .on_hover_text("Copy the nodes and the arrows between them")
+                                .clicked()
+                            {
+                                duplicate = true;
                             }
                         });
+                    });
+                if let Some(color) = recolor {
+                    self.record_state_coalesced();
+                    for node in &mut self.note_nodes {
+                        if self.selected_nodes.contains(&(NodeType::Note, node.id)) {
+                            node.bg_color = color;
+                        }
+                    }
+                    for node in &mut self.code_nodes {
+                        if self.selected_nodes.contains(&(NodeType::Code, node.id)) {
+                            node.bg_color = Some(color);
+                        }
+                    }
+                }
+                if duplicate {
+                    let selection = self.selected_nodes.clone();
+                    self.duplicate_and_select(&selection);
                 }
             }
 
             // Zoom and Offset Display.
+            let zoom_text = if self.zoom.x == self.zoom.y {
+                format!("{:.2}", self.zoom.x)
+            } else {
+                format!("{:.2} x {:.2}", self.zoom.x, self.zoom.y)
+            };
+            let mut status = format!("Zoom: {} | Offset: {:?}", zoom_text, self.offset);
+            if orphaned_connections > 0 {
+                status.push_str(&format!(
+                    " | {} arrow(s) to missing nodes",
+                    orphaned_connections
+                ));
+            }
+            if let Some(last) = self.last_autosave {
+                status.push_str(&format!(" | Autosaved {}s ago", last.elapsed().as_secs()));
+            }
             painter.text(
                 egui::pos2(40.0, 10.0),
                 egui::Align2::LEFT_TOP,
-                format!("Zoom: {:.2} | Offset: {:?}", self.zoom, self.offset),
+                status,
                 egui::TextStyle::Monospace.resolve(ui.style()),
                 ui.visuals().text_color(),
             );
 
+            // Cursor Coordinates: drawn above nodes so they stay readable anywhere on the board.
+            if self.show_cursor_coords {
+                if let Some(pointer) = ctx
+                    .input(|i| i.pointer.hover_pos())
+                    .filter(|pos| response.rect.contains(*pos))
+                {
+                    let canvas_pos = self.to_canvas(pointer);
+                    let coords_painter = ctx.layer_painter(egui::LayerId::new(
+                        egui::Order::Tooltip,
+                        egui::Id::new("cursor_coords"),
+                    ));
+                    let galley = coords_painter.layout_no_wrap(
+                        format!("{:.0}, {:.0}", canvas_pos.x, canvas_pos.y),
+                        egui::TextStyle::Monospace.resolve(ui.style()),
+                        ui.visuals().text_color(),
+                    );
+                    let label_rect =
+                        egui::Rect::from_min_size(pointer + egui::vec2(14.0, 14.0), galley.size())
+                            .expand(2.0);
+                    coords_painter.rect_filled(label_rect, 2.0, ui.visuals().panel_fill);
+                    coords_painter.galley(
+                        label_rect.min + egui::Vec2::splat(2.0),
+                        galley,
+                        ui.visuals().text_color(),
+                    );
+                }
+            }
+
+            // Minimap: the whole board and the visible region, scaled down into a corner.
+            // Clicking or dragging in it centers the view on that point.
+            if self.show_minimap {
+                let minimap_size = egui::vec2(160.0, 120.0);
+                let view = egui::Rect::from_min_max(
+                    self.to_canvas(response.rect.min),
+                    self.to_canvas(response.rect.max),
+                );
+                let bounds = self
+                    .content_bounds()
+                    .map_or(view, |content| content.union(view))
+                    .expand(20.0);
+                let scale = (minimap_size / bounds.size()).min_elem();
+                let minimap_pos = response.rect.right_bottom() - minimap_size - egui::vec2(10.0, 10.0);
+                // Top left of the scaled board, centered in the minimap.
+                let origin = minimap_pos + (minimap_size - bounds.size() * scale) / 2.0;
+                let to_minimap = |p: egui::Pos2| origin + (p - bounds.min) * scale;
+                let mut center_on = None;
+                egui::Area::new("minimap".into())
+                    .fixed_pos(minimap_pos)
+                    .show(ctx, |ui| {
+                        let (rect, minimap_response) =
+                            ui.allocate_exact_size(minimap_size, egui::Sense::click_and_drag());
+                        let painter = ui.painter_at(rect);
+                        painter.rect(
+                            rect,
+                            4.0,
+                            ui.visuals().panel_fill.gamma_multiply(0.9),
+                            egui::Stroke::new(1.0, egui::Color32::from_rgb(80, 80, 80)),
+                            egui::StrokeKind::Inside,
+                        );
+                        let node_rects = self
+                            .note_nodes
+                            .iter()
+                            .map(|n| (n.position, n.size, n.bg_color))
+                            .chain(self.code_nodes.iter().map(|n| {
+                                let background = code_theme(&n.theme).background;
+                                (n.position, n.size, n.bg_color.unwrap_or(background))
+                            }))
+                            .chain(
+                                self.image_nodes
+                                    .iter()
+                                    .map(|n| (n.position, n.size, NOTE_BACKGROUND)),
+                            );
+                        for (position, size, fill) in node_rects {
+                            painter.rect(
+                                egui::Rect::from_min_max(
+                                    to_minimap(position),
+                                    to_minimap(position + size),
+                                ),
+                                0.0,
+                                fill,
+                                egui::Stroke::new(1.0, egui::Color32::from_rgb(120, 120, 120)),
+                                egui::StrokeKind::Inside,
+                            );
+                        }
+                        painter.rect_stroke(
+                            egui::Rect::from_min_max(to_minimap(view.min), to_minimap(view.max)),
+                            0.0,
+                            egui::Stroke::new(1.0, SELECTION_COLOR),
+                            egui::StrokeKind::Inside,
+                        );
+                        if minimap_response.clicked() || minimap_response.dragged() {
+                            center_on = minimap_response
+                                .interact_pointer_pos()
+                                .map(|pos| bounds.min + (pos - origin) / scale);
+                        }
+                    });
+                if let Some(target) = center_on {
+                    self.offset = response.rect.center() - (target.to_vec2() * self.zoom).to_pos2();
+                    self.pan_velocity = egui::Vec2::ZERO;
+                }
+            }
+
             // Tools Overlay.
             egui::Area::new("tool_overlay".into())
                 .fixed_pos(egui::pos2(30.0, 30.0))
@@ -1202,117 +6755,415 @@ impl App for MyApp {
                             }
                             if self.tools_open {
                                 if ui.button("New").clicked() {
-                                    // Clear any previous state.
-                                    self.note_nodes.clear();
-                                    self.code_nodes.clear();
-                                    self.connections.clear();
-                                    self.strokes.clear();
-                                    self.marker_active = false;
-                                    self.eraser_active = false;
-                                    self.arrow_connection_active = false;
-                                    self.connection_start = None;
-                                    self.selected_node = None;
-                                    self.zoom = 2.0;
-                                    self.offset = egui::Vec2::ZERO;
-                                    self.undo_stack.clear();
-                                    self.redo_stack.clear();
-                                    self.record_state();
+                                    self.request_file_action(FileAction::New);
                                 }
-                                if ui.button("Open").clicked() {
-                                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                                        if let Err(e) = self.load_project(path.to_str().unwrap()) {
-                                            eprintln!("Load error: {}", e);
-                                        }
-                                    }
+                                let open_response = ui
+                                    .button("Open")
+                                    .on_hover_text("Right-click to open without history");
+                                if open_response.clicked() {
+                                    self.request_file_action(FileAction::Open);
                                 }
-                                if ui.button("Undo").clicked() {
+                                open_response.context_menu(|ui| {
+                                    if ui
+                                        .button("Open without history")
+                                        .on_hover_text("Load only the current board, for large files")
+                                        .clicked()
+                                    {
+                                        ui.close_menu();
+                                        self.request_file_action(FileAction::OpenWithoutHistory);
+                                    }
+                                });
+                                // A text edit that hasn't been committed yet can be undone too.
+                                let can_undo =
+                                    !self.undo_stack.is_empty() || self.last_text_change.is_some();
+                                if ui
+                                    .add_enabled(can_undo, egui::Button::new("Undo"))
+                                    .on_hover_text(format!("{} step(s) to undo", self.undo_stack.len()))
+                                    .on_disabled_hover_text("Nothing to undo")
+                                    .clicked()
+                                {
                                     self.undo();
                                 }
-                                if ui.button("Redo").clicked() {
+                                if ui
+                                    .add_enabled(!self.redo_stack.is_empty(), egui::Button::new("Redo"))
+                                    .on_hover_text(format!("{} step(s) to redo", self.redo_stack.len()))
+                                    .on_disabled_hover_text("Nothing to redo")
+                                    .clicked()
+                                {
                                     self.redo();
                                 }
-                                if ui.button("Code Node").clicked() {
-                                    if self.project_root.is_none() {
-                                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                                            self.project_root = Some(path);
-                                        }
-                                        if self.project_root.is_none() {
-                                            return;
-                                        }
-                                    }
-                                    // Get the center of the visible area (screen coordinates)
+                                if ui.button("Code Node").clicked() && self.ensure_project_root() {
                                     let visible_center = ctx.input(|i| i.screen_rect().center());
-                                    // Convert visible center to canvas (logical) coordinates.
-                                    let canvas_center = (visible_center - self.offset) / self.zoom;
-                                    // Use next_note_id (or self.code_nodes.len()) to compute an angle.
-                                    let angle = (self.next_note_id as f32) * 45.0_f32.to_radians();
-                                    // Choose a radius in canvas coordinates.
-                                    let radius = 100.0 / self.zoom;
-                                    // Compute new node position relative to the canvas center.
-                                    let new_pos = egui::pos2(
-                                        canvas_center.x + radius * angle.cos(),
-                                        canvas_center.y + radius * angle.sin(),
-                                    );
-                                    self.code_nodes.push(CodeNode {
-                                        id: self.next_note_id,
-                                        position: new_pos,
-                                        size: egui::vec2(300.0, 40.0),
-                                        file_path: String::new(),
-                                        code: String::new(),
-                                        is_dragging: false,
-                                        locked: false,
-                                        line_offset: None,
-                                    });
-                                    self.record_state();
-                                    self.next_note_id += 1;
+                                    let new_pos =
+                                        self.spiral_position(visible_center, self.next_note_id);
+                                    self.add_code_at(new_pos);
                                 }
                                 if ui.button("Note Node").clicked() {
-                                    // Get the center of the visible area (in screen coordinates).
                                     let visible_center = ctx.input(|i| i.screen_rect().center());
-                                    // Convert to canvas coordinates.
-                                    let canvas_center = (visible_center - self.offset) / self.zoom;
-                                    // Use the current count of note nodes to compute an angle.
-                                    let angle =
-                                        (self.note_nodes.len() as f32) * 45.0_f32.to_radians();
-                                    // Choose a radius (in canvas coordinates). Adjust as needed.
-                                    let radius = 100.0 / self.zoom;
-                                    // Compute the new node position.
-                                    let new_pos = egui::pos2(
-                                        canvas_center.x + radius * angle.cos(),
-                                        canvas_center.y + radius * angle.sin(),
-                                    );
-                                    self.note_nodes.push(NoteNode {
-                                        id: self.next_note_id,
-                                        position: new_pos,
-                                        size: egui::vec2(200.0, 40.0),
-                                        text: String::new(),
-                                        is_dragging: false,
-                                        locked: false,
+                                    let new_pos =
+                                        self.spiral_position(visible_center, self.note_nodes.len());
+                                    self.add_note_at(new_pos);
+                                }
+                                if ui.button("Image Node").clicked() {
+                                    let visible_center = ctx.input(|i| i.screen_rect().center());
+                                    let new_pos =
+                                        self.spiral_position(visible_center, self.next_note_id);
+                                    self.add_image_dialog(new_pos);
+                                }
+                                for (tool, name) in [
+                                    (Tool::Select, "Select"),
+                                    (Tool::Marker, "Marker"),
+                                    (Tool::Highlighter, "Highlighter"),
+                                    (Tool::Eraser, "Eraser"),
+                                    (Tool::Arrow, "Arrow"),
+                                    (Tool::Measure, "Measure"),
+                                    (Tool::Line, "Line"),
+                                    (Tool::Rect, "Rect"),
+                                    (Tool::Ellipse, "Ellipse"),
+                                ] {
+                                    if ui
+                                        .add(
+                                            egui::Button::new(name)
+                                                .selected(self.active_tool == tool),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.toggle_tool(tool);
+                                    }
+                                }
+                                let pen = match self.active_tool {
+                                    Tool::Marker => Some((
+                                        &mut self.marker_color,
+                                        &mut self.marker_thickness,
+                                        "Marker",
+                                    )),
+                                    Tool::Highlighter => Some((
+                                        &mut self.highlighter_color,
+                                        &mut self.highlighter_thickness,
+                                        "Highlighter",
+                                    )),
+                                    _ => None,
+                                };
+                                if self.active_tool == Tool::Eraser {
+                                    ui.checkbox(&mut self.stroke_eraser, "Whole strokes")
+                                        .on_hover_text(
+                                            "Erase every stroke the eraser touches in one go",
+                                        );
+                                }
+                                if let Some((color, thickness, name)) = pen {
+                                    egui::color_picker::color_edit_button_srgba(
+                                        ui,
+                                        color,
+                                        egui::color_picker::Alpha::OnlyBlend,
+                                    )
+                                    .on_hover_text(format!("{} color", name));
+                                    ui.add(egui::Slider::new(thickness, 0.5..=40.0).logarithmic(true))
+                                        .on_hover_text(format!("{} thickness", name));
+                                    ui.add(
+                                        egui::Slider::new(&mut self.stroke_smoothing, 0.0..=1.0)
+                                            .text("Smooth"),
+                                    )
+                                    .on_hover_text("Smoothing applied to finished strokes");
+                                }
+                                if self.active_tool == Tool::Marker {
+                                    ui.checkbox(&mut self.marker_speed_width, "Speed width")
+                                        .on_hover_text("Draw thinner lines when moving fast");
+                                }
+                                ui.add(
+                                    egui::DragValue::new(&mut self.connection_hit_radius)
+                                        .range(2.0..=24.0)
+                                        .prefix("Hit: "),
+                                )
+                                .on_hover_text("How close a click must be to select an arrow");
+                                if ui.button("Preferences").clicked() {
+                                    self.show_preferences = !self.show_preferences;
+                                }
+                                let error_count =
+                                    self.log.iter().filter(|e| e.level == LogLevel::Error).count();
+                                let log_label = if error_count > 0 {
+                                    format!("Log ({})", error_count)
+                                } else {
+                                    "Log".to_string()
+                                };
+                                if ui
+                                    .add(egui::Button::new(log_label).selected(self.show_log))
+                                    .on_hover_text("Show load, save and import messages")
+                                    .clicked()
+                                {
+                                    self.show_log = !self.show_log;
+                                }
+                                if ui.button("?").on_hover_text("Shortcuts (F1)").clicked() {
+                                    self.show_shortcuts = !self.show_shortcuts;
+                                }
+                                if ui.button("Reset Zoom").clicked() {
+                                    let center = ctx.screen_rect().center();
+                                    self.set_zoom_about(center, egui::Vec2::splat(2.0));
+                                }
+                                if ui
+                                    .button("Center View")
+                                    .on_hover_text("Pan back to the canvas origin")
+                                    .clicked()
+                                {
+                                    self.center_view(ctx.screen_rect().center());
+                                }
+                                if ui
+                                    .button("Reset View")
+                                    .on_hover_text("Default zoom, centered on the canvas origin")
+                                    .clicked()
+                                {
+                                    self.zoom = egui::Vec2::splat(2.0);
+                                    self.center_view(ctx.screen_rect().center());
+                                }
+                                if ui
+                                    .button("Fit")
+                                    .on_hover_text("Zoom and pan to show the whole board")
+                                    .clicked()
+                                {
+                                    self.fit_to_content(ctx.screen_rect());
+                                }
+                                ui.checkbox(&mut self.snap_to_grid, "Snap")
+                                    .on_hover_text("Snap nodes and shape corners to the grid");
+                                // Grid settings are saved with the project.
+                                let previous_style = self.grid_style;
+                                egui::ComboBox::from_id_salt("grid_style")
+                                    .selected_text(match self.grid_style {
+                                        GridStyle::Lines => "Grid: Lines",
+                                        GridStyle::Dots => "Grid: Dots",
+                                        GridStyle::None => "Grid: None",
+                                    })
+                                    .show_ui(ui, |ui| {
+                                        for (style, name) in [
+                                            (GridStyle::Lines, "Lines"),
+                                            (GridStyle::Dots, "Dots"),
+                                            (GridStyle::None, "None"),
+                                        ] {
+                                            ui.selectable_value(&mut self.grid_style, style, name);
+                                        }
                                     });
-                                    self.record_state();
-                                    self.next_note_id += 1;
+                                let spacing_changed = ui
+                                    .add(
+                                        egui::Slider::new(&mut self.grid_spacing, 5.0..=200.0)
+                                            .text("Spacing"),
+                                    )
+                                    .on_hover_text("Distance between grid lines in canvas units")
+                                    .changed();
+                                let grid_changed =
+                                    spacing_changed || self.grid_style != previous_style;
+                                if grid_changed {
+                                    self.dirty = true;
+                                }
+                                ui.checkbox(&mut self.show_origin, "Origin")
+                                    .on_hover_text("Show the canvas origin axes");
+                                ui.checkbox(&mut self.show_minimap, "Minimap")
+                                    .on_hover_text("Show an overview of the board; click it to jump");
+                                ui.checkbox(&mut self.show_cursor_coords, "Coords")
+                                    .on_hover_text("Show the canvas coordinate under the pointer");
+                                ui.checkbox(&mut self.highlight_orphans, "Orphans")
+                                    .on_hover_text("Outline nodes without any connection");
+                                if self.highlight_orphans
+                                    && ui
+                                        .button("Select Orphans")
+                                        .on_hover_text("Select every node without a connection")
+                                        .clicked()
+                                {
+                                    self.selected_nodes = self.orphan_nodes();
+                                    self.selected_node = match self.selected_nodes.as_slice() {
+                                        &[key] => Some(key),
+                                        _ => None,
+                                    };
+                                }
+                                let all_tags = self.all_tags();
+                                if !all_tags.is_empty() {
+                                    let label = match self.tag_filter.len() {
+                                        0 => "Tags".to_string(),
+                                        count => format!("Tags ({})", count),
+                                    };
+                                    ui.menu_button(label, |ui| {
+                                        for tag in &all_tags {
+                                            let mut on = self.tag_filter.contains(tag);
+                                            let text = egui::RichText::new(tag).color(tag_color(tag));
+                                            if ui.checkbox(&mut on, text).changed() {
+                                                if on {
+                                                    self.tag_filter.insert(tag.clone());
+                                                } else {
+                                                    self.tag_filter.remove(tag);
+                                                }
+                                            }
+                                        }
+                                        if ui.button("Clear").clicked() {
+                                            self.tag_filter.clear();
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text("Dim the nodes without any of the picked tags");
+                                }
+                                if ui.button("Deselect").on_hover_text("Deselect all (Escape)").clicked() {
+                                    self.deselect_all();
                                 }
-                                if ui.button("Marker").clicked() {
-                                    self.marker_active = !self.marker_active;
-                                    self.eraser_active = false;
+                                if ui
+                                    .button("Lock All")
+                                    .on_hover_text("Make every node read-only")
+                                    .clicked()
+                                {
+                                    self.set_all_locked(true);
                                 }
-                                if ui.button("Eraser").clicked() {
-                                    self.eraser_active = !self.eraser_active;
-                                    self.marker_active = false;
+                                if ui.button("Unlock All").clicked() {
+                                    self.set_all_locked(false);
                                 }
-                                if ui.button("Arrow").clicked() {
-                                    self.arrow_connection_active = !self.arrow_connection_active;
-                                    if !self.arrow_connection_active {
-                                        self.connection_start = None;
+                                let search_response = ui.add(
+                                    egui::TextEdit::singleline(&mut self.search_query)
+                                        .hint_text("Search")
+                                        .desired_width(120.0),
+                                );
+                                if !self.search_query.is_empty() {
+                                    let results = self.search_nodes(&self.search_query);
+                                    let mut jump_to = None;
+                                    egui::Area::new("search_results".into())
+                                        .fixed_pos(search_response.rect.left_bottom())
+                                        .order(egui::Order::Foreground)
+                                        .show(ctx, |ui| {
+                                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                                if results.is_empty() {
+                                                    ui.weak("No matches");
+                                                }
+                                                egui::ScrollArea::vertical()
+                                                    .max_height(200.0)
+                                                    .show(ui, |ui| {
+                                                        for (node_type, id, description) in &results {
+                                                            let kind = match node_type {
+                                                                NodeType::Note => "Note",
+                                                                NodeType::Code => "Code",
+                                                                NodeType::Image => "Image",
+                                                            };
+                                                            if ui
+                                                                .selectable_label(
+                                                                    false,
+                                                                    format!("{}: {}", kind, description),
+                                                                )
+                                                                .clicked()
+                                                            {
+                                                                jump_to = Some((*node_type, *id));
+                                                            }
+                                                        }
+                                                    });
+                                            });
+                                        });
+                                    if let Some((node_type, id)) = jump_to {
+                                        self.center_on_node(node_type, id, ctx.screen_rect());
+                                        self.selected_nodes = vec![(node_type, id)];
+                                        self.selected_node = Some((node_type, id));
                                     }
                                 }
-                                if ui.button("Reset Zoom").clicked() {
-                                    self.zoom = 2.0;
+                                if ui
+                                    .checkbox(&mut self.pan_inertia, "Inertia")
+                                    .on_hover_text("Keep panning briefly after a fast drag")
+                                    .changed()
+                                {
+                                    self.pan_velocity = egui::Vec2::ZERO;
                                 }
                                 if ui.button("Save Project").clicked() {
-                                    if let Some(path) = rfd::FileDialog::new().save_file() {
-                                        if let Err(e) = self.save_project(path.to_str().unwrap()) {
-                                            eprintln!("Save error: {}", e);
+                                    self.save_project_dialog();
+                                }
+                                if ui
+                                    .button("Save a Copy")
+                                    .on_hover_text("Write the board to another file (Ctrl+Shift+S)")
+                                    .clicked()
+                                {
+                                    self.save_copy();
+                                }
+                                if ui
+                                    .button("Copy Image")
+                                    .on_hover_text("Copy the whole board to the clipboard as an image")
+                                    .clicked()
+                                {
+                                    if let Err(e) = self.copy_image_to_clipboard() {
+                                        self.log_error(format!("Clipboard error: {}", e));
+                                    }
+                                }
+                                if ui
+                                    .button("Export PNG")
+                                    .on_hover_text("Save the whole board as an image")
+                                    .clicked()
+                                {
+                                    if let Some(path) = self.dialog_path(
+                                        rfd::FileDialog::new()
+                                            .add_filter("PNG", &["png"])
+                                            .set_file_name("board.png")
+                                            .save_file(),
+                                    ) {
+                                        match self.export_png(&path) {
+                                            Ok(()) => self.log_info(format!("Exported {}", path)),
+                                            Err(e) => {
+                                                self.log_error(format!("PNG export error: {}", e))
+                                            }
+                                        }
+                                    }
+                                }
+                                if ui
+                                    .button("Export SVG")
+                                    .on_hover_text("Save the whole board as a vector image")
+                                    .clicked()
+                                {
+                                    if let Some(path) = self.dialog_path(
+                                        rfd::FileDialog::new()
+                                            .add_filter("SVG", &["svg"])
+                                            .set_file_name("board.svg")
+                                            .save_file(),
+                                    ) {
+                                        match self.export_svg(&path) {
+                                            Ok(()) => self.log_info(format!("Exported {}", path)),
+                                            Err(e) => {
+                                                self.log_error(format!("SVG export error: {}", e))
+                                            }
+                                        }
+                                    }
+                                }
+                                ui.add(
+                                    egui::DragValue::new(&mut self.export_scale)
+                                        .range(0.5..=8.0)
+                                        .speed(0.1)
+                                        .prefix("Scale: "),
+                                )
+                                .on_hover_text("Pixels per canvas unit in exported PNGs");
+                                ui.checkbox(&mut self.save_thumbnail_enabled, "Thumbnail")
+                                    .on_hover_text("Also save a preview image next to the project");
+                                if ui.button("Export CSV").clicked() {
+                                    if let Some(path) = self.dialog_path(
+                                        rfd::FileDialog::new()
+                                            .add_filter("CSV", &["csv"])
+                                            .save_file(),
+                                    ) {
+                                        if let Err(e) = self.export_connections_csv(&path) {
+                                            self.log_error(format!("CSV export error: {}", e));
+                                        }
+                                    }
+                                }
+                                if ui.button("Import CSV").clicked() {
+                                    if let Some(path) = self.dialog_path(
+                                        rfd::FileDialog::new()
+                                            .add_filter("CSV", &["csv"])
+                                            .pick_file(),
+                                    ) {
+                                        match self.import_connections_csv(&path) {
+                                            Ok(summary) => {
+                                                self.log_info(format!(
+                                                    "CSV import: added {} connection(s)",
+                                                    summary.added
+                                                ));
+                                                if summary.malformed > 0 {
+                                                    self.log_error(format!(
+                                                        "CSV import: skipped {} row(s) that could not be read",
+                                                        summary.malformed
+                                                    ));
+                                                }
+                                                if summary.missing_nodes > 0 {
+                                                    self.log_error(format!(
+                                                        "CSV import: skipped {} connection(s) with missing endpoints",
+                                                        summary.missing_nodes
+                                                    ));
+                                                }
+                                            }
+                                            Err(e) => self.log_error(format!("CSV import error: {}", e)),
                                         }
                                     }
                                 }
@@ -1321,5 +7172,644 @@ impl App for MyApp {
                     });
                 });
         });
+
+        let mut show_preferences = self.show_preferences;
+        egui::Window::new("Preferences")
+            .open(&mut show_preferences)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut self.preferences.autosave_enabled, "Autosave session")
+                    .on_hover_text(
+                        "Keep the board across restarts, even if it was never saved, and write \
+                         unsaved changes to a recovery file next to the open project",
+                    );
+                ui.add_enabled(
+                    self.preferences.autosave_enabled,
+                    egui::Slider::new(&mut self.preferences.autosave_interval_secs, 10..=300)
+                        .logarithmic(true)
+                        .suffix(" s")
+                        .text("Interval"),
+                );
+                ui.separator();
+                ui.label("Double-click");
+                ui.checkbox(
+                    &mut self.preferences.double_click_edit_node,
+                    "Node: unlock and edit text",
+                );
+                ui.checkbox(
+                    &mut self.preferences.double_click_edit_label,
+                    "Connection: edit label",
+                );
+                ui.checkbox(
+                    &mut self.preferences.double_click_new_note,
+                    "Empty canvas: new note",
+                );
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let mut limited = self.preferences.max_saved_history.is_some();
+                    if ui
+                        .checkbox(&mut limited, "Limit saved history")
+                        .on_hover_text("Keep project files small; undo in the app is not affected")
+                        .changed()
+                    {
+                        self.preferences.max_saved_history = limited.then_some(50);
+                    }
+                    if let Some(max) = &mut self.preferences.max_saved_history {
+                        ui.add(egui::DragValue::new(max).range(0..=10_000).suffix(" steps"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Undo steps kept:")
+                        .on_hover_text("Older steps are dropped to bound memory use");
+                    ui.add(
+                        egui::DragValue::new(&mut self.preferences.max_undo_depth)
+                            .range(1..=10_000),
+                    );
+                });
+            });
+        self.show_preferences = show_preferences;
+
+        if ctx.input(|i| i.key_pressed(egui::Key::F1)) {
+            self.show_shortcuts = !self.show_shortcuts;
+        }
+        let mut show_shortcuts = self.show_shortcuts;
+        egui::Window::new("Shortcuts")
+            .open(&mut show_shortcuts)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("shortcuts").striped(true).show(ui, |ui| {
+                    for (input, action) in [
+                        (
+                            "Drag canvas",
+                            "Pan (Select tool: select strokes in a region)",
+                        ),
+                        ("Scroll wheel / Pinch", "Zoom"),
+                        ("Two-finger scroll", "Pan"),
+                        ("Middle drag / Space + Drag", "Pan with any tool"),
+                        ("Alt + Scroll", "Zoom horizontally"),
+                        ("Click", "Select (Select tool)"),
+                        ("Shift + Click", "Add to or remove from the selection"),
+                        ("Shift + Drag", "45° line, square or circle (shape tools)"),
+                        ("Ctrl + Z", "Undo"),
+                        ("Ctrl + Shift + Z / Ctrl + Y", "Redo"),
+                        ("Ctrl + Shift + S", "Save a copy"),
+                        ("Escape / Click empty canvas", "Deselect all"),
+                        ("Escape in a node's text", "Lock the node"),
+                        ("Delete / Backspace", "Delete the selected node"),
+                        ("Ctrl + D", "Duplicate the selected node"),
+                        ("Ctrl + C", "Copy the selected node"),
+                        ("Ctrl + V", "Paste a node or text at the pointer"),
+                        ("Arrow keys (+ Shift)", "Nudge the selected node by 1 (10)"),
+                        ("F1", "Show or hide this window"),
+                    ] {
+                        ui.label(input);
+                        ui.label(action);
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+                ui.label("Double-click (No tool or Select tool), first match wins:");
+                for (target, action, enabled) in [
+                    (
+                        "1. Node",
+                        "unlock and edit its text (image: options)",
+                        self.preferences.double_click_edit_node,
+                    ),
+                    (
+                        "2. Connection",
+                        "edit its label",
+                        self.preferences.double_click_edit_label,
+                    ),
+                    ("3. Drawing", "nothing", true),
+                    (
+                        "4. Empty canvas",
+                        "new note",
+                        self.preferences.double_click_new_note,
+                    ),
+                ] {
+                    let text = format!("{} → {}", target, action);
+                    if enabled {
+                        ui.label(text);
+                    } else {
+                        ui.weak(format!("{} (off)", text));
+                    }
+                }
+            });
+        self.show_shortcuts = show_shortcuts;
+
+        // Toasts, newest at the bottom.
+        self.toasts
+            .retain(|(_, shown)| shown.elapsed() < TOAST_DURATION);
+        if !self.toasts.is_empty() {
+            egui::Area::new("toasts".into())
+                .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+                .order(egui::Order::Foreground)
+                .show(ctx, |ui| {
+                    for (message, _) in &self.toasts {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(message);
+                        });
+                    }
+                });
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
+
+        // Ask before New or Open replaces a board with unsaved changes.
+        if let Some(action) = self.confirm_discard {
+            let (mut save, mut discard, mut cancel) = (false, false, false);
+            egui::Window::new("Unsaved Changes")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label("Save changes to the current board first?");
+                    ui.horizontal(|ui| {
+                        save = ui.button("Yes").clicked();
+                        discard = ui
+                            .button("No")
+                            .on_hover_text("Discard the changes")
+                            .clicked();
+                        cancel = ui.button("Cancel").clicked();
+                    });
+                });
+            if save || discard || cancel {
+                self.confirm_discard = None;
+            }
+            // After "Yes", only go ahead if the save went through.
+            if (save && self.save_project_dialog()) || discard {
+                self.run_file_action(action);
+            }
+        }
+
+        // Offer to recover unsaved changes to the opened project from its recovery file.
+        if let Some(autosave) = &self.pending_recovery {
+            let mut recover = None;
+            egui::Window::new("Recover Changes")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "{} has unsaved changes that are newer than the project.",
+                        autosave.display()
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Recover").clicked() {
+                            recover = Some(true);
+                        }
+                        if ui.button("Discard").clicked() {
+                            recover = Some(false);
+                        }
+                    });
+                });
+            match recover {
+                Some(true) => {
+                    let autosave = self.pending_recovery.take().unwrap();
+                    match self.load_project(&autosave.to_string_lossy()) {
+                        Ok(()) => {
+                            self.log_info(format!("Recovered {}", autosave.display()));
+                            // Recovered work hasn't been saved to the project file yet.
+                            self.dirty = true;
+                        }
+                        Err(e) => self.log_error(format!("Recovery error: {}", e)),
+                    }
+                }
+                Some(false) => {
+                    self.pending_recovery = None;
+                    self.remove_autosave();
+                }
+                None => {}
+            }
+        }
+
+        // Offer to resume the session found in storage at startup.
+        if let Some(session) = &self.pending_session {
+            let current = &session.current;
+            let node_count =
+                current.note_nodes.len() + current.code_nodes.len() + current.image_nodes.len();
+            let mut resume = None;
+            egui::Window::new("Resume Session")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "The last session had {} node(s) and {} drawing(s).",
+                        node_count,
+                        session.current.strokes.len()
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Resume").clicked() {
+                            resume = Some(true);
+                        }
+                        if ui.button("Discard").clicked() {
+                            resume = Some(false);
+                        }
+                    });
+                });
+            match resume {
+                Some(true) => {
+                    let session = self.pending_session.take().unwrap();
+                    self.undo_stack = session.undo_stack;
+                    self.redo_stack = session.redo_stack;
+                    self.restore_snapshot(session.current);
+                    self.restore_project_root(session.project_root);
+                    self.restore_grid(session.grid_spacing, session.grid_style);
+                    // Resumed work hasn't been saved to a file yet.
+                    self.dirty = true;
+                }
+                Some(false) => self.pending_session = None,
+                None => {}
+            }
+        }
+    }
+
+    // Called by eframe periodically and on close, so unsaved work survives a restart.
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        match serde_json::to_string(&self.preferences) {
+            Ok(json) => storage.set_string(PREFERENCES_KEY, json),
+            Err(e) => self.log_error(format!("Preferences save error: {}", e)),
+        }
+        storage.set_string(TOOLS_OPEN_KEY, self.tools_open.to_string());
+        // Until the user answers the resume prompt, keep the stored session as it is.
+        if self.pending_session.is_some() {
+            return;
+        }
+        if !self.preferences.autosave_enabled {
+            // Don't offer an outdated session next time.
+            storage.set_string(SESSION_KEY, String::new());
+            return;
+        }
+        match serde_json::to_string(&self.project_history()) {
+            Ok(json) => {
+                storage.set_string(SESSION_KEY, json);
+                self.last_autosave = Some(Instant::now());
+            }
+            Err(e) => self.log_error(format!("Session save error: {}", e)),
+        }
+        self.write_autosave();
+    }
+
+    fn auto_save_interval(&self) -> Duration {
+        Duration::from_secs(self.preferences.autosave_interval_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_lines_cover_the_range() {
+        let cases: [(f32, f32, f32, &[f32]); 4] = [
+            (0.0, 100.0, 25.0, &[0.0, 25.0, 50.0, 75.0, 100.0]),
+            (-30.0, 10.0, 10.0, &[-30.0, -20.0, -10.0, 0.0, 10.0]),
+            (-12.0, 12.0, 10.0, &[-20.0, -10.0, 0.0, 10.0, 20.0]),
+            // An empty range.
+            (10.0, 0.0, 5.0, &[]),
+        ];
+        for (min, max, spacing, expected) in cases {
+            let lines: Vec<f32> = grid_lines(min, max, spacing).collect();
+            assert_eq!(lines, expected, "grid_lines({}, {}, {})", min, max, spacing);
+        }
+    }
+
+    #[test]
+    fn grid_lines_survive_extreme_spacing() {
+        let cases: [(f32, f32, f32, &[f32]); 5] = [
+            // Spacing that isn't finite or is below one unit falls back to one unit.
+            (0.0, 3.0, f32::NAN, &[0.0, 1.0, 2.0, 3.0]),
+            (0.0, 3.0, f32::INFINITY, &[0.0, 1.0, 2.0, 3.0]),
+            (0.0, 3.0, 1e-9, &[0.0, 1.0, 2.0, 3.0]),
+            (0.0, 3.0, 0.0, &[0.0, 1.0, 2.0, 3.0]),
+            // Huge spacing leaves the lines around the range.
+            (0.0, 100.0, 1e30, &[0.0, 1e30]),
+        ];
+        for (min, max, spacing, expected) in cases {
+            let lines: Vec<f32> = grid_lines(min, max, spacing).collect();
+            assert_eq!(lines, expected, "grid_lines({}, {}, {})", min, max, spacing);
+        }
+    }
+
+    #[test]
+    fn grid_lines_skip_ranges_that_are_not_finite() {
+        for (min, max) in [
+            (f32::NAN, 100.0),
+            (0.0, f32::NAN),
+            (f32::NEG_INFINITY, 0.0),
+            (0.0, f32::INFINITY),
+            (-f32::MAX, f32::MAX),
+        ] {
+            assert_eq!(
+                grid_lines(min, max, 25.0).count(),
+                0,
+                "range {}..={}",
+                min,
+                max
+            );
+        }
+    }
+
+    #[test]
+    fn grid_lines_stop_at_the_line_limit() {
+        let spacing = 25.0;
+        let at_limit = grid_lines(0.0, MAX_GRID_LINES * spacing, spacing).count();
+        assert_eq!(at_limit, MAX_GRID_LINES as usize + 1);
+        let past_limit = grid_lines(0.0, (MAX_GRID_LINES + 1.0) * spacing, spacing).count();
+        assert_eq!(past_limit, 0);
+    }
+
+    fn test_stroke() -> Stroke {
+        Stroke {
+            points: vec![
+                egui::pos2(0.0, 0.0),
+                egui::pos2(10.0, 5.0),
+                egui::pos2(20.0, 0.0),
+            ],
+            color: egui::Color32::BLACK,
+            thickness: 2.0,
+            kind: StrokeKind::Marker,
+            widths: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn marker_stroke_records_once_per_cycle() {
+        let mut app = MyApp::default();
+        for cycle in 1..=2 {
+            app.current_stroke = Some(test_stroke());
+            // Frames with the pointer down, then a few after the release.
+            for pointer_down in [true, true, false, false, false] {
+                app.finish_stroke(pointer_down);
+            }
+            assert_eq!(app.undo_stack.len(), cycle);
+            assert_eq!(app.strokes.len(), cycle);
+        }
+    }
+
+    #[test]
+    fn erase_gesture_records_once_per_cycle() {
+        let mut app = MyApp::default();
+        for cycle in 1..=2 {
+            for (pointer_down, hit) in [(true, false), (true, true), (true, true), (false, false)] {
+                app.record_erase(pointer_down, hit);
+            }
+            assert_eq!(app.undo_stack.len(), cycle);
+        }
+    }
+
+    #[test]
+    fn ctrl_z_undoes_without_a_focused_text_field() {
+        let mut app = MyApp::default();
+        app.record_state();
+        app.record_state();
+        let ctx = egui::Context::default();
+        let raw_input = egui::RawInput {
+            modifiers: egui::Modifiers::COMMAND,
+            events: vec![egui::Event::Key {
+                key: egui::Key::Z,
+                physical_key: None,
+                pressed: true,
+                repeat: false,
+                modifiers: egui::Modifiers::COMMAND,
+            }],
+            ..Default::default()
+        };
+        let _ = ctx.run(raw_input, |ctx| {
+            let input = ctx.input(|i| i.clone());
+            app.handle_shortcuts(ctx, &input);
+        });
+        assert_eq!(app.undo_stack.len(), 1);
+        assert_eq!(app.redo_stack.len(), 1);
+    }
+
+    #[test]
+    fn zoom_keeps_the_point_under_the_cursor() {
+        let mut app = MyApp {
+            offset: egui::vec2(-120.0, 45.0),
+            ..MyApp::default()
+        };
+        let anchor = egui::pos2(310.0, 220.0);
+        let before = app.to_canvas(anchor);
+        for zoom in [2.5, 0.4, 4.0] {
+            app.set_zoom_about(anchor, egui::Vec2::splat(zoom));
+            let after = app.to_canvas(anchor);
+            assert!(
+                before.distance(after) < 1e-3,
+                "{:?} moved to {:?}",
+                before,
+                after
+            );
+        }
+    }
+
+    #[test]
+    fn deleting_a_node_removes_its_connections() {
+        let mut app = MyApp::default();
+        let first = app.insert_note(egui::pos2(0.0, 0.0));
+        let second = app.add_linked_note(NodeType::Note, first).unwrap();
+        assert_eq!(app.connections.len(), 1);
+        app.delete_node(NodeType::Note, second);
+        assert!(app.connections.is_empty());
+    }
+
+    #[test]
+    fn undo_stack_stays_capped() {
+        let mut app = MyApp::default();
+        app.preferences.max_undo_depth = 5;
+        for i in 0..8 {
+            app.record_state();
+            app.insert_note(egui::pos2(i as f32 * 10.0, 0.0));
+        }
+        assert_eq!(app.undo_stack.len(), 5);
+        // Undo walks back through the window only.
+        for _ in 0..6 {
+            app.undo();
+        }
+        assert_eq!(app.note_nodes.len(), 3);
+        assert_eq!(app.redo_stack.len(), 5);
+        for _ in 0..5 {
+            app.redo();
+        }
+        assert_eq!(app.note_nodes.len(), 8);
+        assert_eq!(app.undo_stack.len(), 5);
+    }
+
+    #[test]
+    fn recovery_is_offered_only_for_a_newer_autosave() {
+        let dir = std::env::temp_dir().join(format!("cnf_infinity_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let project = dir.join("board.json");
+        let autosave = autosave_path(&project);
+        assert_eq!(autosave, dir.join("board.json.autosave"));
+        File::create(&project).unwrap();
+        assert_eq!(newer_autosave(&project), None);
+
+        let now = std::time::SystemTime::now();
+        File::create(&autosave).unwrap().set_modified(now).unwrap();
+        File::options()
+            .write(true)
+            .open(&project)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(newer_autosave(&project), Some(autosave.clone()));
+
+        File::options()
+            .write(true)
+            .open(&project)
+            .unwrap()
+            .set_modified(now + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(newer_autosave(&project), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn large_board_paints_only_what_is_visible() {
+        let mut app = MyApp {
+            show_minimap: false,
+            ..MyApp::default()
+        };
+        for row in 0..50 {
+            for column in 0..60 {
+                app.insert_note(egui::pos2(column as f32 * 300.0, row as f32 * 300.0));
+            }
+        }
+        let ctx = egui::Context::default();
+        let raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(1280.0, 800.0),
+            )),
+            ..Default::default()
+        };
+        let mut frame = Frame::_new_kittest();
+        let output = ctx.run(raw_input, |ctx| app.update(ctx, &mut frame));
+        // Thousands of nodes, of which only the few on screen are painted.
+        assert!(output.shapes.len() < app.note_nodes.len() / 10);
+    }
+
+    #[test]
+    fn deleting_a_note_keeps_the_code_node_selected() {
+        let mut app = MyApp::default();
+        let note = app.insert_note(egui::pos2(0.0, 0.0));
+        app.insert_note(egui::pos2(0.0, 100.0));
+        let code = app.insert_code(egui::pos2(300.0, 0.0));
+        app.selected_node = Some((NodeType::Code, code));
+        app.delete_node(NodeType::Note, note);
+        assert!(app.selected_node == Some((NodeType::Code, code)));
+        assert!(app.code_nodes.iter().any(|n| n.id == code));
+    }
+
+    #[test]
+    fn undo_removes_an_added_node() {
+        let mut app = MyApp::default();
+        app.add_note_at(egui::pos2(0.0, 0.0));
+        app.add_code_at(egui::pos2(300.0, 0.0));
+        app.undo();
+        assert!(app.code_nodes.is_empty());
+        assert_eq!(app.note_nodes.len(), 1);
+        app.undo();
+        assert!(app.note_nodes.is_empty());
+    }
+
+    #[test]
+    fn undo_keeps_the_view() {
+        let mut app = MyApp::default();
+        app.record_state();
+        app.insert_note(egui::pos2(0.0, 0.0));
+        app.offset = egui::vec2(250.0, -80.0);
+        app.undo();
+        assert!(app.note_nodes.is_empty());
+        assert_eq!(app.offset, egui::vec2(250.0, -80.0));
+    }
+
+    #[test]
+    fn note_colors_default_when_missing_or_null() {
+        let mut note = serde_json::json!({
+            "id": 1,
+            "position": [0.0, 0.0],
+            "size": [200.0, 40.0],
+            "text": "",
+            "is_dragging": false,
+            "locked": false,
+        });
+        let parsed: NoteNode = serde_json::from_value(note.clone()).unwrap();
+        assert_eq!(parsed.bg_color, NOTE_BACKGROUND);
+        assert_eq!(parsed.text_color, NOTE_TEXT_COLOR);
+        note["bg_color"] = serde_json::Value::Null;
+        note["text_color"] = serde_json::json!([10, 20, 30, 255]);
+        let parsed: NoteNode = serde_json::from_value(note).unwrap();
+        assert_eq!(parsed.bg_color, NOTE_BACKGROUND);
+        assert_eq!(parsed.text_color, egui::Color32::from_rgb(10, 20, 30));
+    }
+
+    #[test]
+    fn undo_restores_text_from_before_typing() {
+        let mut app = MyApp::default();
+        let id = app.add_note_at(egui::pos2(0.0, 0.0));
+        app.note_nodes[0].text = "Draft".to_owned();
+        app.record_state();
+        let ctx = egui::Context::default();
+        // Focus the note and type a few keystrokes, then leave the field.
+        app.track_text_edits(&ctx, true, false, false);
+        for text in ["Draft.", "Draft. M", "Draft. More"] {
+            app.note_nodes[0].text = text.to_owned();
+            app.track_text_edits(&ctx, false, true, false);
+        }
+        app.track_text_edits(&ctx, false, false, true);
+        app.undo();
+        let note = app.note_nodes.iter().find(|n| n.id == id).unwrap();
+        assert_eq!(note.text, "Draft");
+    }
+
+    #[test]
+    fn constrained_shapes_stay_constrained_on_the_grid() {
+        let spacing = 25.0;
+        let start = egui::pos2(50.0, -75.0);
+        for kind in [ShapeKind::Line, ShapeKind::Rect, ShapeKind::Ellipse] {
+            for pos in [
+                egui::pos2(130.0, 20.0),
+                egui::pos2(-61.0, -190.0),
+                egui::pos2(3.0, 9.0),
+            ] {
+                let end = shape_end(kind, start, snap_to_grid(pos, spacing), true, Some(spacing));
+                assert_eq!(snap_to_grid(end, spacing), end);
+                let delta = end - start;
+                let axis_aligned = delta.x == 0.0 || delta.y == 0.0;
+                match kind {
+                    ShapeKind::Line => assert!(axis_aligned || delta.x.abs() == delta.y.abs()),
+                    _ => assert_eq!(delta.x.abs(), delta.y.abs(), "{:?}", pos),
+                }
+                // Without Shift the pointer position is used as is.
+                assert_eq!(shape_end(kind, start, pos, false, Some(spacing)), pos);
+            }
+        }
+    }
+
+    #[test]
+    fn strong_text_stands_out_from_its_color() {
+        let luma =
+            |c: egui::Color32| 0.299 * c.r() as f32 + 0.587 * c.g() as f32 + 0.114 * c.b() as f32;
+        let light = NOTE_TEXT_COLOR;
+        assert!(luma(strong_color(light)) > luma(light));
+        let dark = egui::Color32::from_rgb(40, 40, 60);
+        assert!(luma(strong_color(dark)) < luma(dark));
+    }
+
+    #[test]
+    fn undo_removes_a_new_connection() {
+        let mut app = MyApp::default();
+        let first = app.insert_note(egui::pos2(0.0, 0.0));
+        let second = app.insert_code(egui::pos2(300.0, 0.0));
+        app.connection_start = Some((first, NodeType::Note, Side::Right));
+        app.connect_to(NodeType::Code, second, Side::Left);
+        assert_eq!(app.connections.len(), 1);
+        assert!(app.connection_start.is_none());
+        app.undo();
+        assert!(app.connections.is_empty());
     }
 }